@@ -0,0 +1,117 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use ldk_node::bip39::Mnemonic;
+use ldk_node::bitcoin::hashes::{sha256, Hash};
+use rusqlite::{Connection, DatabaseName, OpenFlags};
+use serde::{Deserialize, Serialize};
+
+use crate::crypto::{self, seal_at_rest};
+use crate::recovery::unix_now;
+use crate::store::open_state_file;
+
+const BACKUP_MAGIC: &[u8] = b"SCBK1";
+const BACKUP_FILE_NAME: &str = "stable-channels.backup";
+
+/// The LDK store, which holds the channel monitors.
+const NODE_FILES: &[&str] = &["ldk_node_data.sqlite"];
+
+/// The app's own state: the stable agreement and where the channel is at.
+const STATE_FILES: &[&str] = &["stable_channel.json", "lifecycle.json", "onchain_log.json"];
+
+#[derive(Serialize, Deserialize)]
+struct BackupBundle {
+    created_at: i64,
+    /// (location, file name, hex contents)
+    files: Vec<(String, String, String)>,
+}
+
+/// Key the backup is encrypted with. It comes from the wallet seed, so the
+/// recovery phrase is all that's needed to open it.
+pub fn backup_key(mnemonic: &Mnemonic) -> [u8; 32] {
+    let mut data = mnemonic.to_seed_normalized("").to_vec();
+    data.extend_from_slice(b"stable-channels-backup");
+    sha256::Hash::hash(&data).to_byte_array()
+}
+
+/// Writes an encrypted backup of the channel monitors and the stable channel
+/// state to `dest_dir`, replacing the previous one.
+pub fn export_backup(key: &[u8; 32], node_dir: &Path, state_dir: &Path, dest_dir: &Path) -> Result<PathBuf, String> {
+    let mut files = Vec::new();
+    for name in NODE_FILES {
+        let path = node_dir.join(name);
+        if path.exists() {
+            let contents = copy_sqlite(&path)?;
+            files.push(("node".to_string(), name.to_string(), hex::encode(contents)));
+        }
+    }
+    for name in STATE_FILES {
+        if let Ok(contents) = fs::read(state_dir.join(name)) {
+            // The backup has its own key, so don't nest the passphrase encryption.
+            let contents = open_state_file(contents)?;
+            files.push(("state".to_string(), name.to_string(), hex::encode(contents)));
+        }
+    }
+
+    let bundle = BackupBundle {
+        created_at: unix_now(),
+        files,
+    };
+    let json = serde_json::to_vec(&bundle).map_err(|e| format!("Failed to build backup: {}", e))?;
+    let mut data = BACKUP_MAGIC.to_vec();
+    data.extend(crypto::seal(key, &json)?);
+
+    // Write next to the old backup first so a failure never leaves a broken one.
+    let path = dest_dir.join(BACKUP_FILE_NAME);
+    let tmp_path = dest_dir.join(format!("{}.tmp", BACKUP_FILE_NAME));
+    fs::write(&tmp_path, data)
+        .and_then(|_| fs::rename(&tmp_path, &path))
+        .map_err(|e| format!("Failed to write backup to {}: {}", dest_dir.display(), e))?;
+    Ok(path)
+}
+
+/// A consistent copy of the sqlite database at `path`, which the node may be
+/// writing to. Goes through SQLite's backup API rather than reading the file,
+/// which could catch it halfway through a write.
+fn copy_sqlite(path: &Path) -> Result<Vec<u8>, String> {
+    let tmp_path = path.with_extension("sqlite.backup-tmp");
+    let result = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .and_then(|conn| conn.backup(DatabaseName::Main, &tmp_path, None))
+        .map_err(|e| format!("Failed to copy the channel state: {}", e))
+        .and_then(|_| fs::read(&tmp_path).map_err(|e| format!("Failed to copy the channel state: {}", e)));
+    let _ = fs::remove_file(&tmp_path);
+    result
+}
+
+/// Restores a backup into the node and state directories. Must run before
+/// the node is built, and refuses to overwrite an existing node's channel
+/// state. Returns when the backup was made.
+pub fn import_backup(key: &[u8; 32], backup_path: &Path, node_dir: &Path, state_dir: &Path) -> Result<i64, String> {
+    if NODE_FILES.iter().any(|name| node_dir.join(name).exists()) {
+        return Err("This data directory already has channel state, restore into an empty one".to_string());
+    }
+
+    let data = fs::read(backup_path).map_err(|e| format!("Failed to read backup: {}", e))?;
+    let sealed = data
+        .strip_prefix(BACKUP_MAGIC)
+        .ok_or("Not a Stable Channels backup")?;
+    let json = crypto::open(key, sealed).map_err(|_| "This backup is for a different recovery phrase".to_string())?;
+    let bundle: BackupBundle = serde_json::from_slice(&json).map_err(|e| format!("Invalid backup: {}", e))?;
+
+    for (location, name, contents) in &bundle.files {
+        let dir = match location.as_str() {
+            "node" if NODE_FILES.contains(&name.as_str()) => node_dir,
+            "state" if STATE_FILES.contains(&name.as_str()) => state_dir,
+            _ => return Err(format!("Unexpected file {} in backup", name)),
+        };
+        let mut contents = hex::decode(contents).map_err(|e| format!("Invalid backup: {}", e))?;
+        if location == "state" {
+            contents = seal_at_rest(contents)?;
+        }
+        fs::create_dir_all(dir)
+            .and_then(|_| fs::write(dir.join(name), contents))
+            .map_err(|e| format!("Failed to restore {}: {}", name, e))?;
+    }
+
+    Ok(bundle.created_at)
+}
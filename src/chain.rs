@@ -0,0 +1,100 @@
+use std::error::Error;
+
+use ldk_node::bitcoin::OutPoint;
+use serde_json::Value;
+use ureq::Agent;
+
+/// Where a transaction stands on the chain.
+#[derive(Debug, Clone)]
+pub struct TxConfirmation {
+    pub txid: String,
+    pub block_height: Option<u32>,
+}
+
+impl TxConfirmation {
+    pub fn confirmations(&self, tip_height: u32) -> u32 {
+        match self.block_height {
+            Some(height) if tip_height >= height => tip_height - height + 1,
+            _ => 0,
+        }
+    }
+}
+
+/// Queries the Esplora chain source configured for the node.
+pub struct Esplora {
+    agent: Agent,
+    base_url: String,
+}
+
+impl Esplora {
+    pub fn new(agent: Agent, base_url: &str) -> Self {
+        Self {
+            agent,
+            base_url: base_url.trim_end_matches('/').to_string(),
+        }
+    }
+
+    fn get_json(&self, path: &str) -> Result<Value, Box<dyn Error>> {
+        let url = format!("{}{}", self.base_url, path);
+        Ok(self.agent.get(&url).call()?.into_json()?)
+    }
+
+    pub fn tip_height(&self) -> Result<u32, Box<dyn Error>> {
+        let url = format!("{}/blocks/tip/height", self.base_url);
+        let height = self.agent.get(&url).call()?.into_string()?;
+        Ok(height.trim().parse()?)
+    }
+
+    pub fn tx_status(&self, txid: &str) -> Result<TxConfirmation, Box<dyn Error>> {
+        let status = self.get_json(&format!("/tx/{}/status", txid))?;
+        Ok(TxConfirmation {
+            txid: txid.to_string(),
+            block_height: confirmed_height(&status),
+        })
+    }
+
+    /// Confirmed and mempool transactions touching `address`, newest first,
+    /// in Esplora's JSON format.
+    pub fn address_txs(&self, address: &str) -> Result<Vec<Value>, Box<dyn Error>> {
+        let txs = self.get_json(&format!("/address/{}/txs", address))?;
+        Ok(txs.as_array().cloned().unwrap_or_default())
+    }
+
+    /// Fee rate in sat/vB expected to confirm within `target_blocks`.
+    pub fn fee_rate(&self, target_blocks: u16) -> Result<f64, Box<dyn Error>> {
+        let estimates = self.get_json("/fee-estimates")?;
+        estimates
+            .get(target_blocks.to_string())
+            .and_then(|rate| rate.as_f64())
+            .ok_or_else(|| format!("No fee estimate for {} blocks", target_blocks).into())
+    }
+
+    /// The transaction spending `outpoint`, if any. For a channel funding
+    /// output this is the closing transaction.
+    pub fn spending_tx(&self, outpoint: &OutPoint) -> Result<Option<TxConfirmation>, Box<dyn Error>> {
+        let outspend = self.get_json(&format!("/tx/{}/outspend/{}", outpoint.txid, outpoint.vout))?;
+        if !outspend.get("spent").and_then(|s| s.as_bool()).unwrap_or(false) {
+            return Ok(None);
+        }
+
+        let txid = outspend
+            .get("txid")
+            .and_then(|t| t.as_str())
+            .ok_or("Esplora outspend has no txid")?;
+        let block_height = outspend.get("status").and_then(confirmed_height);
+        Ok(Some(TxConfirmation {
+            txid: txid.to_string(),
+            block_height,
+        }))
+    }
+}
+
+pub fn confirmed_height(status: &Value) -> Option<u32> {
+    if !status.get("confirmed").and_then(|c| c.as_bool()).unwrap_or(false) {
+        return None;
+    }
+    status
+        .get("block_height")
+        .and_then(|h| h.as_u64())
+        .map(|h| h as u32)
+}
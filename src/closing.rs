@@ -0,0 +1,158 @@
+use ldk_node::bitcoin::OutPoint;
+use ldk_node::lightning::ln::types::ChannelId;
+use ldk_node::{BalanceDetails, LightningBalance, PendingSweepBalance};
+
+use crate::chain::{Esplora, TxConfirmation};
+
+/// On-chain progress of a channel close, as seen by the chain source.
+#[derive(Default)]
+pub struct ClosingProgress {
+    pub tip_height: u32,
+    pub closing_tx: Option<TxConfirmation>,
+    pub sweep_tx: Option<TxConfirmation>,
+    pub error: Option<String>,
+}
+
+impl ClosingProgress {
+    pub fn fetch(esplora: &Esplora, funding_txo: Option<OutPoint>, sweep_txid: Option<&str>) -> Self {
+        let mut progress = ClosingProgress::default();
+
+        match esplora.tip_height() {
+            Ok(height) => progress.tip_height = height,
+            Err(e) => {
+                progress.error = Some(format!("Chain source unavailable: {}", e));
+                return progress;
+            }
+        }
+
+        if let Some(funding_txo) = funding_txo {
+            match esplora.spending_tx(&funding_txo) {
+                Ok(closing_tx) => progress.closing_tx = closing_tx,
+                Err(e) => progress.error = Some(format!("Failed to look up closing transaction: {}", e)),
+            }
+        }
+
+        if let Some(sweep_txid) = sweep_txid {
+            match esplora.tx_status(sweep_txid) {
+                Ok(sweep_tx) => progress.sweep_tx = Some(sweep_tx),
+                Err(e) => progress.error = Some(format!("Failed to look up sweep transaction: {}", e)),
+            }
+        }
+
+        progress
+    }
+}
+
+/// One chunk of channel funds on its way back to the on-chain wallet.
+pub struct ClosingBalance {
+    pub channel_id: Option<ChannelId>,
+    pub amount_sats: u64,
+    pub status: String,
+}
+
+fn blocks_left(target_height: u32, tip_height: u32) -> String {
+    let blocks = target_height.saturating_sub(tip_height);
+    // Roughly ten minutes a block.
+    format!("{} blocks (~{} min)", blocks, blocks * 10)
+}
+
+/// Describes every balance LDK is still claiming from closed or closing
+/// channels, including those time-locked behind `to_self_delay`.
+pub fn closing_balances(balances: &BalanceDetails, tip_height: u32) -> Vec<ClosingBalance> {
+    let mut lines = Vec::new();
+
+    for balance in &balances.lightning_balances {
+        let (channel_id, amount_sats, status) = match balance {
+            LightningBalance::ClaimableOnChannelClose { channel_id, amount_satoshis, .. } => (
+                *channel_id,
+                *amount_satoshis,
+                "Claimable once the closing transaction confirms".to_string(),
+            ),
+            LightningBalance::ClaimableAwaitingConfirmations {
+                channel_id,
+                amount_satoshis,
+                confirmation_height,
+                ..
+            } => (
+                *channel_id,
+                *amount_satoshis,
+                format!("Time-locked, spendable in {}", blocks_left(*confirmation_height, tip_height)),
+            ),
+            LightningBalance::ContentiousClaimable { channel_id, amount_satoshis, timeout_height, .. } => (
+                *channel_id,
+                *amount_satoshis,
+                format!("In-flight payment, claimable before {}", blocks_left(*timeout_height, tip_height)),
+            ),
+            LightningBalance::MaybeTimeoutClaimableHTLC { channel_id, amount_satoshis, claimable_height, .. } => (
+                *channel_id,
+                *amount_satoshis,
+                format!("Expired payment, refundable in {}", blocks_left(*claimable_height, tip_height)),
+            ),
+            LightningBalance::MaybePreimageClaimableHTLC { channel_id, amount_satoshis, expiry_height, .. } => (
+                *channel_id,
+                *amount_satoshis,
+                format!("Incoming payment, expires in {}", blocks_left(*expiry_height, tip_height)),
+            ),
+            LightningBalance::CounterpartyRevokedOutputClaimable { channel_id, amount_satoshis, .. } => (
+                *channel_id,
+                *amount_satoshis,
+                "Penalty claim against a revoked state".to_string(),
+            ),
+        };
+        lines.push(ClosingBalance {
+            channel_id: Some(channel_id),
+            amount_sats,
+            status,
+        });
+    }
+
+    for balance in &balances.pending_balances_from_channel_closures {
+        let (channel_id, amount_sats, status) = match balance {
+            PendingSweepBalance::PendingBroadcast { channel_id, amount_satoshis } => (
+                *channel_id,
+                *amount_satoshis,
+                "Claim waiting to be broadcast".to_string(),
+            ),
+            PendingSweepBalance::BroadcastAwaitingConfirmation {
+                channel_id,
+                latest_spending_txid,
+                amount_satoshis,
+                ..
+            } => (
+                *channel_id,
+                *amount_satoshis,
+                format!("Claim {} broadcast, waiting for confirmation", latest_spending_txid),
+            ),
+            PendingSweepBalance::AwaitingThresholdConfirmations {
+                channel_id,
+                latest_spending_txid,
+                confirmation_height,
+                amount_satoshis,
+                ..
+            } => (
+                *channel_id,
+                *amount_satoshis,
+                format!(
+                    "Claim {} confirmed {} blocks ago",
+                    latest_spending_txid,
+                    tip_height.saturating_sub(*confirmation_height) + 1
+                ),
+            ),
+        };
+        lines.push(ClosingBalance {
+            channel_id,
+            amount_sats,
+            status,
+        });
+    }
+
+    lines
+}
+
+/// Whether LDK is still claiming funds from `channel_id`. Sweeps with an
+/// unknown channel are counted too, to be safe.
+pub fn claims_pending(balances: &BalanceDetails, channel_id: ChannelId) -> bool {
+    closing_balances(balances, 0)
+        .iter()
+        .any(|balance| balance.channel_id.map_or(true, |id| id == channel_id))
+}
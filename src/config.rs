@@ -1,10 +1,55 @@
 use serde::Deserialize;
+use std::path::PathBuf;
+use dirs_next as dirs;
+use ldk_node::bitcoin::Network;
+use ureq::{Agent, AgentBuilder, Proxy};
+
+use crate::notify::NotificationKind;
+use crate::pricing::PricingMode;
+use crate::types::{PegTarget, USD};
 
 #[derive(Deserialize, Debug)]
 pub struct LspConfig {
     pub pubkey: String,
     pub address: String,
     pub auth: String,
+    /// LSPS2 opening fee parameters, used to estimate the fee before paying.
+    /// ldk-node doesn't expose the LSP's fee menu, so set these to what the
+    /// LSP advertises.
+    #[serde(default = "default_opening_fee_min_msat")]
+    pub opening_fee_min_msat: u64,
+    #[serde(default = "default_opening_fee_ppm")]
+    pub opening_fee_ppm: u64,
+    /// How long the LSP may be unreachable while the app is running before
+    /// we offer to force-close.
+    #[serde(default = "default_offline_threshold_secs")]
+    pub offline_threshold_secs: u64,
+    /// Bolt12 offer of the LSP's node. Changes to the stable amount are
+    /// sent to it, without it the amount can't be changed.
+    #[serde(default)]
+    pub offer: Option<String>,
+}
+
+fn default_opening_fee_min_msat() -> u64 {
+    2_000_000
+}
+
+fn default_opening_fee_ppm() -> u64 {
+    10_000
+}
+
+fn default_offline_threshold_secs() -> u64 {
+    6 * 3600
+}
+
+impl LspConfig {
+    /// Opening fee the LSP takes out of a JIT payment of `payment_msat`,
+    /// following the LSPS2 `opening_fee` formula.
+    pub fn opening_fee_msat(&self, payment_msat: u64) -> u64 {
+        let proportional = (payment_msat as u128 * self.opening_fee_ppm as u128)
+            .div_ceil(1_000_000) as u64;
+        proportional.max(self.opening_fee_min_msat)
+    }
 }
 
 #[derive(Deserialize, Debug)]
@@ -14,12 +59,185 @@ pub struct NodeConfig {
     pub data_dir: String,
     pub alias: String,
     pub port: u16,
+    /// Proxy for price feeds and the chain source, e.g.
+    /// `socks5://127.0.0.1:9050` for Tor. Lightning peer connections,
+    /// including the LSP, don't go through it. It is set as HTTPS_PROXY and
+    /// HTTP_PROXY for the whole process, since that's the only way to hand it
+    /// to LDK's chain source.
+    #[serde(default)]
+    pub proxy: Option<String>,
+}
+
+impl NodeConfig {
+    /// An HTTP agent going through `proxy`, if one is set.
+    pub fn http_agent(&self) -> Agent {
+        let mut builder = AgentBuilder::new();
+        if let Some(proxy) = &self.proxy {
+            builder = builder.proxy(Proxy::new(proxy).expect("Invalid proxy in configuration file."));
+        }
+        builder.build()
+    }
+
+    /// `proxy` the way reqwest, which LDK's chain source uses, wants it:
+    /// `socks5h` so host names are resolved by the proxy rather than leaked
+    /// to the local resolver.
+    pub fn chain_source_proxy(&self) -> Option<String> {
+        let proxy = self.proxy.as_deref()?;
+        Some(match proxy.split_once("://") {
+            Some(("socks5" | "socks", rest)) => format!("socks5h://{}", rest),
+            Some(_) => proxy.to_string(),
+            None => format!("http://{}", proxy),
+        })
+    }
+
+    pub fn network(&self) -> Network {
+        match self.network.to_lowercase().as_str() {
+            "signet" => Network::Signet,
+            "testnet" => Network::Testnet,
+            "bitcoin" => Network::Bitcoin,
+            _ => Network::Signet,
+        }
+    }
 }
 
 #[derive(Deserialize, Debug)]
 pub struct StableChannelConfig {
     pub expected_usd: f64,
+    /// If set, peg this percentage of the channel balance instead of `expected_usd`.
+    #[serde(default)]
+    pub stable_percent: Option<f64>,
     pub sc_dir: String,
+    /// Limits on the dollar amount a user can stabilize when onboarding.
+    #[serde(default = "default_min_usd")]
+    pub min_usd: f64,
+    #[serde(default = "default_max_usd")]
+    pub max_usd: f64,
+    #[serde(default = "default_invoice_expiry_secs")]
+    pub invoice_expiry_secs: u32,
+    /// Upper bound on the LSP fee we accept for opening the JIT channel.
+    #[serde(default = "default_max_lsp_fee_msat")]
+    pub max_lsp_fee_msat: u64,
+    /// Price new channels stabilize at. It is sent to the LSP with the rest
+    /// of the channel agreement, and a channel keeps the mode it was opened
+    /// with.
+    #[serde(default)]
+    pub pricing_mode: PricingMode,
+}
+
+fn default_min_usd() -> f64 {
+    5.0
+}
+
+fn default_max_usd() -> f64 {
+    1_000.0
+}
+
+fn default_invoice_expiry_secs() -> u32 {
+    3600
+}
+
+fn default_max_lsp_fee_msat() -> u64 {
+    10_000_000
+}
+
+impl StableChannelConfig {
+    pub fn peg_target(&self) -> PegTarget {
+        match self.stable_percent {
+            Some(percent) => PegTarget::Percent(percent),
+            None => PegTarget::FixedUsd(USD::from_f64(self.expected_usd)),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct NotificationConfig {
+    /// Also show notifications through the operating system.
+    #[serde(default = "default_os_notifications")]
+    pub os_notifications: bool,
+    /// Which events notify. All of them by default.
+    #[serde(default = "default_notify_on")]
+    pub notify_on: Vec<NotificationKind>,
+}
+
+fn default_os_notifications() -> bool {
+    true
+}
+
+fn default_notify_on() -> Vec<NotificationKind> {
+    NotificationKind::ALL.to_vec()
+}
+
+impl Default for NotificationConfig {
+    fn default() -> Self {
+        Self {
+            os_notifications: default_os_notifications(),
+            notify_on: default_notify_on(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct PriceConfig {
+    /// Stream prices from exchange WebSockets where a feed has one, instead
+    /// of polling every feed.
+    #[serde(default)]
+    pub streaming: bool,
+    /// How old a streamed price may get before the feed is polled instead.
+    #[serde(default = "default_stream_max_age_secs")]
+    pub stream_max_age_secs: u64,
+    /// Seconds between stability checks. Worth lowering when streaming.
+    #[serde(default = "default_check_interval_secs")]
+    pub check_interval_secs: u64,
+    /// Feeds polled in addition to the built-in ones.
+    #[serde(default)]
+    pub feeds: Vec<FeedConfig>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct FeedConfig {
+    pub name: String,
+    pub url: String,
+    #[serde(flatten)]
+    pub format: FeedFormat,
+}
+
+/// How to read the price out of a feed's response.
+#[derive(Deserialize, Debug)]
+#[serde(tag = "format", rename_all = "snake_case")]
+pub enum FeedFormat {
+    /// JSON, with a path like `result.XXBTZUSD.c[0]`.
+    Json { path: String },
+    /// CSV with a header row; the price is in `column` of the last row.
+    Csv { column: String },
+    /// A signed oracle attestation, see `price_sources::SignedOracle`.
+    Oracle {
+        pubkey: String,
+        #[serde(default = "default_oracle_max_age_secs")]
+        max_age_secs: i64,
+    },
+}
+
+fn default_oracle_max_age_secs() -> i64 {
+    300
+}
+
+fn default_stream_max_age_secs() -> u64 {
+    30
+}
+
+fn default_check_interval_secs() -> u64 {
+    30
+}
+
+impl Default for PriceConfig {
+    fn default() -> Self {
+        Self {
+            streaming: false,
+            stream_max_age_secs: default_stream_max_age_secs(),
+            check_interval_secs: default_check_interval_secs(),
+            feeds: Vec::new(),
+        }
+    }
 }
 
 #[derive(Deserialize, Debug)]
@@ -27,13 +245,35 @@ pub struct Config {
     pub lsp: LspConfig,
     pub node: NodeConfig,
     pub stable_channel_defaults: StableChannelConfig,
+    #[serde(default)]
+    pub notifications: NotificationConfig,
+    #[serde(default)]
+    pub prices: PriceConfig,
 }
 
 impl Config {
     pub fn from_file(path: &str) -> Self {
         let content = std::fs::read_to_string(path)
             .expect("Unable to read configuration file.");
-        toml::from_str(&content)
-            .expect("Invalid format in configuration file.")
+        let config: Config = toml::from_str(&content)
+            .expect("Invalid format in configuration file.");
+        // Better to stop here than to quietly connect without the proxy.
+        if let Some(proxy) = &config.node.proxy {
+            Proxy::new(proxy).expect("Invalid proxy in configuration file.");
+        }
+        config
+    }
+
+    /// Storage directory of the LDK node.
+    pub fn node_dir(&self) -> PathBuf {
+        let mut dir = dirs::home_dir().unwrap();
+        dir.push(&self.node.data_dir);
+        dir.push(&self.node.alias);
+        dir
+    }
+
+    /// Directory holding the app's own stable channel state.
+    pub fn stable_channel_dir(&self) -> PathBuf {
+        self.node_dir().join(&self.stable_channel_defaults.sc_dir)
     }
 }
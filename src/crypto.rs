@@ -0,0 +1,172 @@
+use std::fs;
+use std::path::Path;
+use std::sync::RwLock;
+
+use argon2::Argon2;
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use serde::{Deserialize, Serialize};
+
+const NONCE_LEN: usize = 12;
+
+/// Encrypts `plaintext` with a fresh random nonce, which is prepended to the
+/// ciphertext.
+pub fn seal(key: &[u8; 32], plaintext: &[u8]) -> Result<Vec<u8>, String> {
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(key));
+    let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let ciphertext = cipher
+        .encrypt(&nonce, plaintext)
+        .map_err(|_| "Encryption failed".to_string())?;
+
+    let mut sealed = nonce.to_vec();
+    sealed.extend_from_slice(&ciphertext);
+    Ok(sealed)
+}
+
+/// Reverses `seal`. Fails if the key is wrong or the data was tampered with.
+pub fn open(key: &[u8; 32], sealed: &[u8]) -> Result<Vec<u8>, String> {
+    if sealed.len() < NONCE_LEN {
+        return Err("Encrypted data is truncated".to_string());
+    }
+    let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(key));
+    cipher
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| "Wrong key or corrupted data".to_string())
+}
+
+/// Key for encrypting the app's files at rest, set once the user has
+/// entered their passphrase at launch.
+static DATA_KEY: RwLock<Option<[u8; 32]>> = RwLock::new(None);
+
+/// Where the passphrase salt and check value live, in the node directory.
+const ENCRYPTION_FILE: &str = "encryption.json";
+
+/// Known plaintext sealed with the data key, to tell a wrong passphrase
+/// apart from corrupted files.
+const CHECK_VALUE: &[u8] = b"stable-channels";
+
+pub fn set_data_key(key: [u8; 32]) {
+    *DATA_KEY.write().unwrap_or_else(|e| e.into_inner()) = Some(key);
+}
+
+/// Forgets the data key, so nothing more gets written with it.
+pub fn clear_data_key() {
+    *DATA_KEY.write().unwrap_or_else(|e| e.into_inner()) = None;
+}
+
+pub fn data_key() -> Option<[u8; 32]> {
+    *DATA_KEY.read().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Encrypts file contents if at-rest encryption is on.
+pub fn seal_at_rest(data: Vec<u8>) -> Result<Vec<u8>, String> {
+    match data_key() {
+        Some(key) => seal(&key, &data),
+        None => Ok(data),
+    }
+}
+
+/// Decrypts file contents if at-rest encryption is on. Fails rather than
+/// hand back data it couldn't decrypt.
+pub fn open_at_rest(data: Vec<u8>) -> Result<Vec<u8>, String> {
+    match data_key() {
+        Some(key) => open(&key, &data),
+        None => Ok(data),
+    }
+}
+
+fn derive_key(passphrase: &str, salt: &[u8]) -> Result<[u8; 32], String> {
+    let mut key = [0u8; 32];
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| format!("Failed to derive key: {}", e))?;
+    Ok(key)
+}
+
+/// Salt and check value for the passphrase. Stored in plaintext next to the
+/// node data; neither reveals anything about the passphrase.
+#[derive(Serialize, Deserialize)]
+pub struct EncryptionParams {
+    salt: String,
+    check: String,
+}
+
+impl EncryptionParams {
+    /// Sets up a new passphrase, returning its parameters and the data key.
+    pub fn create(passphrase: &str) -> Result<(Self, [u8; 32]), String> {
+        let mut salt = [0u8; 16];
+        OsRng.fill_bytes(&mut salt);
+        let key = derive_key(passphrase, &salt)?;
+        let params = Self {
+            salt: hex::encode(salt),
+            check: hex::encode(seal(&key, CHECK_VALUE)?),
+        };
+        Ok((params, key))
+    }
+
+    /// Derives the data key from `passphrase`, failing if it is wrong.
+    pub fn unlock(&self, passphrase: &str) -> Result<[u8; 32], String> {
+        let salt = hex::decode(&self.salt).map_err(|_| "Invalid encryption settings".to_string())?;
+        let check = hex::decode(&self.check).map_err(|_| "Invalid encryption settings".to_string())?;
+        let key = derive_key(passphrase, &salt)?;
+        match open(&key, &check) {
+            Ok(value) if value == CHECK_VALUE => Ok(key),
+            _ => Err("Wrong passphrase".to_string()),
+        }
+    }
+
+    pub fn load(node_dir: &Path) -> Option<Self> {
+        let content = fs::read_to_string(node_dir.join(ENCRYPTION_FILE)).ok()?;
+        serde_json::from_str(&content).ok()
+    }
+
+    pub fn save(&self, node_dir: &Path) -> Result<(), String> {
+        let json = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        fs::write(node_dir.join(ENCRYPTION_FILE), json)
+            .map_err(|e| format!("Failed to save encryption settings: {}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seal_then_open_round_trips() {
+        let key = [7u8; 32];
+        let sealed = seal(&key, b"recovery phrase").unwrap();
+        assert_ne!(&sealed[NONCE_LEN..], b"recovery phrase");
+        assert_eq!(open(&key, &sealed).unwrap(), b"recovery phrase");
+    }
+
+    #[test]
+    fn sealing_twice_uses_fresh_nonces() {
+        let key = [7u8; 32];
+        assert_ne!(seal(&key, b"data").unwrap(), seal(&key, b"data").unwrap());
+    }
+
+    #[test]
+    fn open_fails_with_wrong_key() {
+        let sealed = seal(&[1u8; 32], b"secret").unwrap();
+        assert!(open(&[2u8; 32], &sealed).is_err());
+    }
+
+    #[test]
+    fn open_fails_on_tampered_or_truncated_data() {
+        let key = [3u8; 32];
+        let mut sealed = seal(&key, b"secret").unwrap();
+        let last = sealed.len() - 1;
+        sealed[last] ^= 1;
+        assert!(open(&key, &sealed).is_err());
+        assert!(open(&key, &sealed[..NONCE_LEN - 1]).is_err());
+    }
+
+    #[test]
+    fn unlock_accepts_only_the_right_passphrase() {
+        let (params, key) = EncryptionParams::create("correct horse").unwrap();
+        assert_eq!(params.unlock("correct horse").unwrap(), key);
+        assert_eq!(params.unlock("wrong horse").unwrap_err(), "Wrong passphrase");
+    }
+}
@@ -0,0 +1,76 @@
+use serde::{Deserialize, Serialize};
+
+use crate::types::StableChannel;
+
+/// Minimum spacing between recorded points, so a month of history stays small
+/// enough to rewrite on every save.
+const RECORD_INTERVAL_SECS: i64 = 120;
+
+/// How far back history is kept.
+pub const MAX_AGE_SECS: i64 = 30 * 86_400;
+
+/// The price and the stable receiver's balance at one stability check.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PricePoint {
+    pub timestamp: i64,
+    pub median_price: f64,
+    /// Quote from each price feed, by feed name.
+    pub quotes: Vec<(String, f64)>,
+    pub receiver_sats: u64,
+    pub receiver_usd: f64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PriceHistory {
+    pub points: Vec<PricePoint>,
+}
+
+impl PriceHistory {
+    /// Records the state after a stability check. Returns whether a point was
+    /// added, in which case the history should be saved.
+    pub fn record(&mut self, sc: &StableChannel, now: i64) -> bool {
+        if sc.spot_price <= 0.0 {
+            return false;
+        }
+        if let Some(last) = self.points.last() {
+            if now - last.timestamp < RECORD_INTERVAL_SECS {
+                return false;
+            }
+        }
+
+        self.points.push(PricePoint {
+            timestamp: now,
+            median_price: sc.spot_price,
+            quotes: sc
+                .prices
+                .iter()
+                .filter_map(|q| q.price.map(|price| (q.name.clone(), price)))
+                .collect(),
+            receiver_sats: sc.stable_receiver_btc.sats,
+            receiver_usd: sc.stable_receiver_usd.0,
+        });
+        self.points.retain(|point| now - point.timestamp <= MAX_AGE_SECS);
+        true
+    }
+
+    /// Points from the last `window_secs` seconds, oldest first.
+    pub fn window(&self, window_secs: i64, now: i64) -> &[PricePoint] {
+        let start = self
+            .points
+            .partition_point(|point| now - point.timestamp > window_secs);
+        &self.points[start..]
+    }
+
+    /// Names of every feed seen in `points`, in order of first appearance.
+    pub fn feed_names(points: &[PricePoint]) -> Vec<String> {
+        let mut names: Vec<String> = Vec::new();
+        for point in points {
+            for (name, _) in &point.quotes {
+                if !names.contains(name) {
+                    names.push(name.clone());
+                }
+            }
+        }
+        names
+    }
+}
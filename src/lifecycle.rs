@@ -0,0 +1,73 @@
+use ldk_node::lightning::ln::types::ChannelId;
+use ldk_node::{BalanceDetails, ChannelDetails};
+use serde::{Deserialize, Serialize};
+
+use crate::closing::claims_pending;
+
+/// Where the stable channel is in its life, from onboarding to the funds
+/// being back on-chain. Persisted so a restart resumes on the right screen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ChannelLifecycle {
+    /// Nothing open yet, the user still has to onboard.
+    NoChannel,
+    /// A JIT invoice or deposit address is out, waiting for the payment.
+    JitPending,
+    /// The funding transaction is out, waiting for confirmations.
+    ChannelPending,
+    /// The channel is open and being stabilized.
+    Ready,
+    /// The user asked to close, the channel hasn't closed yet.
+    Closing,
+    /// The channel is closed, funds are time-locked or not yet swept.
+    ClosedSweepPending,
+    /// All channel funds are back in the on-chain wallet.
+    Swept,
+}
+
+impl ChannelLifecycle {
+    /// Works out the current stage from what the node reports, starting from
+    /// the last known stage. LDK doesn't tell us when a close was requested or
+    /// a JIT invoice handed out, so those stages are kept until the node shows
+    /// the next one.
+    pub fn derive(
+        previous: ChannelLifecycle,
+        channels: &[ChannelDetails],
+        balances: &BalanceDetails,
+        stable_channel_id: ChannelId,
+    ) -> ChannelLifecycle {
+        // Before the first channel is known any channel is ours; after that only
+        // the stable channel counts, so closing it isn't masked by another one.
+        let channel = if stable_channel_id == ChannelId::from_bytes([0; 32]) {
+            channels.first()
+        } else {
+            channels.iter().find(|c| c.channel_id == stable_channel_id)
+        };
+
+        if let Some(channel) = channel {
+            return match previous {
+                ChannelLifecycle::Closing => ChannelLifecycle::Closing,
+                _ if channel.is_channel_ready => ChannelLifecycle::Ready,
+                _ => ChannelLifecycle::ChannelPending,
+            };
+        }
+
+        let funds_in_flight = claims_pending(balances, stable_channel_id);
+
+        match previous {
+            ChannelLifecycle::JitPending => ChannelLifecycle::JitPending,
+            _ if funds_in_flight => ChannelLifecycle::ClosedSweepPending,
+            ChannelLifecycle::NoChannel => ChannelLifecycle::NoChannel,
+            // Had a channel, it's gone and nothing is left to claim.
+            _ => ChannelLifecycle::Swept,
+        }
+    }
+
+    /// Called on startup. Unpaid JIT invoices aren't persisted, so a pending
+    /// onboarding starts over.
+    pub fn on_restart(self) -> ChannelLifecycle {
+        match self {
+            ChannelLifecycle::JitPending => ChannelLifecycle::NoChannel,
+            other => other,
+        }
+    }
+}
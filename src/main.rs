@@ -2,74 +2,272 @@ mod config;
 mod stable;
 mod types;
 mod price_feeds;
+mod store;
+mod payments;
+mod qr;
+mod lifecycle;
+mod chain;
+mod closing;
+mod recovery;
+mod wallet;
+mod seed;
+mod crypto;
+mod backup;
+mod tray;
+mod notify;
+mod history;
+mod price_sources;
+mod price_stream;
+mod pricing;
 
 use eframe::{egui, App, Frame};
-use egui::{epaint::{self, Margin}, TextureHandle, TextureOptions};
-use image::{GrayImage, Luma};
+use egui::epaint::{self, Margin};
 use ldk_node::{
-    bitcoin::{address, secp256k1::PublicKey, Address, Network}, lightning::{ln::{msgs::SocketAddress, types::ChannelId}, offers::offer::Offer}, lightning_invoice::{Bolt11InvoiceDescription, Description}, Builder, ChannelDetails, Event, Node
+    bitcoin::{address, secp256k1::PublicKey, Address}, lightning::{ln::{msgs::SocketAddress, types::ChannelId}, offers::offer::Offer}, lightning_invoice::{Bolt11InvoiceDescription, Description}, Builder, ChannelDetails, Event, Node
 };
 
+use ldk_node::bip39::Mnemonic;
+use ldk_node::bitcoin::hashes::Hash;
 use ldk_node::lightning::routing::gossip::NodeId;
 
 
 use egui::{TextStyle, TextWrapMode};
 use egui::{Color32, Grid};
 use egui_extras::{Column, TableBuilder};
+use egui_plot::{Legend, Line, Plot};
 
 
-use qrcode::{Color, QrCode};
-use std::{fs, path::PathBuf, str::FromStr, time::{Duration, Instant}};
+use std::{fs, path::PathBuf, str::FromStr, sync::mpsc, thread, time::{Duration, Instant}};
 use dirs_next as dirs;
 
 use crate::config::Config;
-use crate::stable::{check_stability, close_channels_to_address, get_latest_price};
-use crate::types::{Bitcoin, StableChannel, USD};
+use crate::stable::{
+    apply_peg_target, check_stability, close_channel, StabilityOutcome, estimate_close_fees, get_latest_price, refresh_price,
+    stabilization_record, sweep_to_address, validate_withdrawal_address, StabilizationRecord,
+    decrease_stable_balance, increase_stable_balance, is_stabilization_payment, move_to_native, move_to_stable, update_balances,
+    received_agreement, send_agreement, AgreementUpdate, StableAgreement,
+};
+use crate::store::{
+    load_backup_confirmed, load_channel_backup_settings, load_lifecycle, load_onchain_log, load_price_history,
+    load_stable_channel, reseal_state_files, save_backup_confirmed, save_channel_backup_settings, save_lifecycle,
+    load_stabilizations, save_onchain_log, save_price_history, save_stabilizations, save_stable_channel,
+    ChannelBackupSettings,
+};
+use crate::backup::{backup_key, export_backup, import_backup};
+use crate::crypto::{clear_data_key, data_key, set_data_key, EncryptionParams};
+use crate::seed::{
+    encrypt_seed_files, find_seed, plaintext_seed_files_exist, remove_plaintext_seed_files, generate_mnemonic, parse_mnemonic, save_mnemonic, seed_file_path, show_words,
+    BackupQuiz, WalletSeed,
+};
+use crate::lifecycle::ChannelLifecycle;
+use crate::chain::Esplora;
+use crate::closing::{claims_pending, closing_balances, ClosingProgress};
+use crate::payments::{bip21_uri, parse_payment_request, pay, PaymentRequest};
+use crate::qr::QrView;
+use crate::tray::{Tray, TrayAction};
+use crate::notify::{NotificationKind, Notifier};
+use crate::history::{PriceHistory, PricePoint};
+use crate::price_feeds::{init_price_service, price_service};
+use crate::pricing::PricingMode;
+use crate::recovery::{
+    force_close_channel, force_close_explanation, format_duration, is_connected, offline_secs, spend_delay_blocks, unix_now,
+};
+use crate::types::{Bitcoin, PegTarget, StableChannel, USD};
+use crate::wallet::{fetch_history, received_at, OnchainLog, OnchainTx};
 
 enum AppState {
     OnboardingScreen,
     WaitingForPayment,
     MainScreen,
-    ClosingScreen
+    SendScreen,
+    ReceiveScreen,
+    ClosingScreen,
+    OnchainScreen,
+    BackupScreen,
+    ChartScreen,
+}
+
+/// What the user pays to open a JIT stable channel of a given size.
+#[derive(Clone, Copy)]
+struct JitQuote {
+    stable_usd: USD,
+    invoice_msat: u64,
+    lsp_fee_msat: u64,
+}
+
+impl JitQuote {
+    /// What the on-chain fallback asks for. We open that channel ourselves,
+    /// so it has to cover the stable amount and the funding fee rather than
+    /// the LSP's fee.
+    fn deposit_sats(&self) -> u64 {
+        let stable_sats = (self.invoice_msat - self.lsp_fee_msat) / 1000;
+        (self.invoice_msat / 1000).max(stable_sats + OPEN_CHANNEL_FEE_RESERVE_SATS)
+    }
+}
+
+/// Time windows the price chart can show.
+const CHART_WINDOWS: [(&str, i64); 4] = [
+    ("1H", 3600),
+    ("24H", 86_400),
+    ("7D", 7 * 86_400),
+    ("30D", 30 * 86_400),
+];
+
+/// A channel close waiting for the user to confirm it.
+struct CloseConfirmation {
+    channel: ChannelDetails,
+    address: String,
+    closing_fee_sats: u64,
+    sweep_fee_sats: u64,
+}
+
+/// Progress of the onboarding JIT payment, driven by node events. The
+/// incoming HTLC waits at the LSP until our channel exists, and ldk-node
+/// reports nothing about it before that, so these stages are as fine as the
+/// status can get.
+#[derive(Clone, Copy, PartialEq)]
+enum JitStatus {
+    AwaitingPayment,
+    DepositDetected,
+    OpeningChannel,
+    ReceivingPayment,
+}
+
+/// The screen to show for a lifecycle stage.
+fn screen_for(lifecycle: ChannelLifecycle) -> AppState {
+    match lifecycle {
+        ChannelLifecycle::NoChannel => AppState::OnboardingScreen,
+        ChannelLifecycle::JitPending | ChannelLifecycle::ChannelPending => AppState::WaitingForPayment,
+        ChannelLifecycle::Ready => AppState::MainScreen,
+        ChannelLifecycle::Closing
+        | ChannelLifecycle::ClosedSweepPending
+        | ChannelLifecycle::Swept => AppState::ClosingScreen,
+    }
 }
 
 struct MyApp {
     state: AppState,
+    lifecycle: ChannelLifecycle,
     last_stability_check: Instant,
     invoice_result: String,
     user: Node,
-    qr_texture: Option<TextureHandle>,
+    invoice_qr: QrView,
     channel_list: Vec<ChannelDetails>,
     stable_channel: StableChannel,
     close_channel_address: String,
     status_message: String,
     config: Config,  // store our loaded config
+    adjust_amount: String,
+    receive_amount: String,
+    receive_invoice: String,
+    receive_qr: QrView,
+    send_input: String,
+    send_amount: String,
+    send_request: Option<PaymentRequest>,
+    pending_stable_sends: Vec<([u8; 32], USD)>,
+    /// The LSP's offer from the config, where agreement updates are paid to.
+    lsp_offer: Option<Offer>,
+    agreement_update: Option<AgreementUpdate>,
+    jit_amount: String,
+    active_jit_quote: Option<JitQuote>,
+    jit_status: Option<JitStatus>,
+    invoice_expires_at: Option<Instant>,
+    deposit_address: String,
+    next_deposit_check: Instant,
+    closing_progress: ClosingProgress,
+    last_closing_refresh: Option<Instant>,
+    close_channel_id: Option<ChannelId>,
+    close_confirmation: Option<CloseConfirmation>,
+    force_close_acknowledged: bool,
+    onchain_log: OnchainLog,
+    onchain_address: String,
+    onchain_qr: QrView,
+    onchain_send_address: String,
+    onchain_send_amount: String,
+    onchain_send_all: bool,
+    onchain_history: Vec<OnchainTx>,
+    onchain_fee_rate: Option<f64>,
+    onchain_error: Option<String>,
+    last_onchain_refresh: Option<Instant>,
+    mnemonic: Option<Mnemonic>,
+    backup_confirmed: bool,
+    backup_quiz: BackupQuiz,
+    channel_backup: ChannelBackupSettings,
+    channel_backup_due: bool,
+    /// When `channel_backup_due` was first seen, to let a burst of changes settle.
+    channel_backup_due_since: Option<Instant>,
+    /// The export running in the background, if any.
+    channel_backup_job: Option<mpsc::Receiver<Result<PathBuf, String>>>,
+    new_passphrase: String,
+    confirm_passphrase: String,
+    tray: Option<Tray>,
+    stabilization_paused: bool,
+    quitting: bool,
+    notifier: Notifier,
+    high_risk_notified: bool,
+    /// Stabilization payments sent and received, with the price each was made at.
+    stabilizations: Vec<StabilizationRecord>,
+    price_history: PriceHistory,
+    chart_window_secs: i64,
+    chart_show_feeds: bool,
 }
 
-fn make_node(config: &Config, lsp_pubkey: Option<PublicKey>) -> Node {
+/// Shown where the stable amount would change but there's no LSP offer to
+/// send the change to.
+const NO_LSP_OFFER_NOTE: &str = "Set the LSP's offer in config.toml to change your stable balance. Your LSP has to be told of the new amount, or it keeps stabilizing against the old one.";
+
+/// Kept on-chain when opening a channel from a deposit, to pay the funding fee.
+const OPEN_CHANNEL_FEE_RESERVE_SATS: u64 = 2_000;
+
+/// Wait after the channel state changes before backing it up, so a burst of
+/// events makes one backup.
+const CHANNEL_BACKUP_DELAY: Duration = Duration::from_secs(5);
+
+/// How often the deposit address is looked up on the chain source.
+const DEPOSIT_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Wait before trying again when opening a channel from a deposit fails.
+const DEPOSIT_RETRY_DELAY: Duration = Duration::from_secs(120);
+
+fn make_node(config: &Config, lsp_pubkey: Option<PublicKey>, seed: &WalletSeed) -> Node {
     println!("Config used for make_node: {:?}", config);
 
     let mut builder = Builder::new();
+    // Without a recovery phrase LDK falls back to the `keys_seed` file.
+    match seed {
+        WalletSeed::Mnemonic(mnemonic) => {
+            builder.set_entropy_bip39_mnemonic(mnemonic.clone(), None);
+        }
+        WalletSeed::SeedBytes(bytes) => {
+            if let Err(e) = builder.set_entropy_seed_bytes(bytes.clone()) {
+                panic!("Invalid wallet seed: {:?}", e);
+            }
+        }
+        WalletSeed::SeedFile(_) | WalletSeed::None => {}
+    }
     if let Some(lsp_pubkey) = lsp_pubkey {
         let address = config.lsp.address.parse().unwrap();
         println!("Setting LSP with address: {} and pubkey: {:?}", address, lsp_pubkey);
         builder.set_liquidity_source_lsps2(address, lsp_pubkey, Some(config.lsp.auth.clone()));
     }
 
-    let network = match config.node.network.to_lowercase().as_str() {
-        "signet" => Network::Signet,
-        "testnet" => Network::Testnet,
-        "bitcoin" => Network::Bitcoin,
-        _ => Network::Signet,
-    };
+    let network = config.node.network();
     println!("Network set to: {:?}", network);
 
     builder.set_network(network);
+    // LDK has no proxy setting for its chain source, but the HTTP client
+    // underneath picks one up from the environment. That makes it
+    // process-wide: any other client reading HTTPS_PROXY goes through it too.
+    // Ours are built from `http_agent()`, which uses the same proxy anyway.
+    if let Some(proxy) = config.node.chain_source_proxy() {
+        println!("Chain source going through proxy {}", proxy);
+        println!("Lightning peer connections are not proxied");
+        std::env::set_var("HTTPS_PROXY", &proxy);
+        std::env::set_var("HTTP_PROXY", &proxy);
+    }
     builder.set_chain_source_esplora(config.node.chain_source_url.clone(), None);
 
-    let mut dir = dirs::home_dir().unwrap();
-    dir.push(&config.node.data_dir);
-    dir.push(&config.node.alias);
+    let dir = config.node_dir();
     println!("Storage directory: {:?}", dir);
 
     if !dir.exists() {
@@ -106,16 +304,38 @@ fn make_node(config: &Config, lsp_pubkey: Option<PublicKey>) -> Node {
     node
 }
 
-
 impl MyApp {
-    fn new(_cc: &eframe::CreationContext<'_>, config: Config) -> Self {
+    fn new(config: Config, seed: WalletSeed) -> Self {
         let lsp_pubkey_bytes = hex::decode(&config.lsp.pubkey).unwrap();
         let lsp_pubkey = PublicKey::from_slice(&lsp_pubkey_bytes).unwrap();
         println!("{}", lsp_pubkey);
 
-        let user = make_node(&config, Some(lsp_pubkey));
+        let mnemonic = match &seed {
+            WalletSeed::Mnemonic(mnemonic) => Some(mnemonic.clone()),
+            _ => None,
+        };
+        let user = make_node(&config, Some(lsp_pubkey), &seed);
+        init_price_service(config.node.http_agent(), &config.prices.feeds);
+        if config.prices.streaming && config.node.proxy.is_some() {
+            println!("Price streaming can't go through the proxy, polling instead");
+        } else if config.prices.streaming {
+            price_service().start_streaming(Duration::from_secs(config.prices.stream_max_age_secs));
+        }
         
+        let lsp_offer = config.lsp.offer.as_deref().and_then(|offer| match Offer::from_str(offer) {
+            Ok(offer) => Some(offer),
+            Err(e) => {
+                eprintln!("Invalid LSP offer in config: {:?}", e);
+                None
+            }
+        });
+        if lsp_offer.is_none() {
+            println!("No LSP offer configured, the stable balance can't be changed");
+        }
+
         let channels = user.list_channels();
+        let spot_price = get_latest_price();
+        let price_history = load_price_history(&config.stable_channel_dir()).unwrap_or_default();
         
         let channel_id = if !channels.is_empty() {
             channels[0].channel_id
@@ -123,11 +343,13 @@ impl MyApp {
             ChannelId::from_bytes([0; 32])
         };
 
-        let stable_channel = StableChannel {
+        let mut stable_channel = StableChannel {
             channel_id,
             is_stable_receiver: true,
             counterparty: lsp_pubkey,
+            peg_target: config.stable_channel_defaults.peg_target(),
             expected_usd: USD::from_f64(config.stable_channel_defaults.expected_usd),
+            native_btc: Bitcoin::from_sats(0),
             expected_btc: Bitcoin::from_btc(0.0),
             stable_receiver_btc: Bitcoin::from_btc(0.0),
             stable_provider_btc: Bitcoin::from_btc(0.0),
@@ -138,30 +360,129 @@ impl MyApp {
             formatted_datetime: "2021-06-01 12:00:00".to_string(),
             payment_made: false,
             sc_dir: config.stable_channel_defaults.sc_dir.clone(),
-            latest_price: get_latest_price(),
-            prices: "".to_string(),
+            latest_price: spot_price,
+            spot_price,
+            pricing_mode: config.stable_channel_defaults.pricing_mode,
+            price_samples: price_history
+                .points
+                .iter()
+                .map(|point| (point.timestamp, point.median_price))
+                .collect(),
+            prices: Vec::new(),
+            prices_updated: None,
+            funding_txo: None,
+            sweep_txid: None,
+            withdrawal_address: None,
+            onchain_before_close_sats: None,
+            counterparty_last_seen: None,
+            pending_stable_receives: Vec::new(),
+            agreement_pending: false,
         };
+        if !channels.is_empty() {
+            update_balances(&mut stable_channel, Some(channels[0].clone()));
+            match load_stable_channel(&config.stable_channel_dir()) {
+                Some(saved) if saved.channel_id() == Some(channel_id) => {
+                    saved.apply_to(&mut stable_channel);
+                    if stable_channel.pricing_mode != config.stable_channel_defaults.pricing_mode {
+                        println!(
+                            "The channel stabilizes at the {} price agreed with the LSP, pricing_mode in config.toml only applies to new channels",
+                            stable_channel.pricing_mode.label()
+                        );
+                    }
+                }
+                _ => {
+                    apply_peg_target(&mut stable_channel);
+                    if let Err(e) = save_stable_channel(&config.stable_channel_dir(), &stable_channel) {
+                        eprintln!("Failed to save stable channel: {}", e);
+                    }
+                }
+            }
+        }
         println!("Stable Channel created: {:?}", stable_channel.channel_id.to_string());
 
-        // TODO = check if channel is closing, how?
-        let state = if channels.len() > 1 {
-            AppState::ClosingScreen
-        } else if channel_id != ChannelId::from_bytes([0; 32]) {
-            AppState::MainScreen
-        } else {
-            AppState::OnboardingScreen
-        };
+        let saved_lifecycle = load_lifecycle(&config.stable_channel_dir())
+            .unwrap_or(ChannelLifecycle::NoChannel)
+            .on_restart();
+        let lifecycle = ChannelLifecycle::derive(
+            saved_lifecycle,
+            &channels,
+            &user.list_balances(),
+            channel_id,
+        );
+        if let Err(e) = save_lifecycle(&config.stable_channel_dir(), lifecycle) {
+            eprintln!("Failed to save channel lifecycle: {}", e);
+        }
+        println!("Channel lifecycle: {:?}", lifecycle);
+
+        // A channel that is still confirming came from onboarding, keep showing its progress.
+        let jit_status = (lifecycle == ChannelLifecycle::ChannelPending)
+            .then_some(JitStatus::OpeningChannel);
+
+        let notifier = Notifier::new(
+            config.notifications.notify_on.clone(),
+            config.notifications.os_notifications,
+        );
 
         Self {
-            state,
+            state: screen_for(lifecycle),
+            lifecycle,
             last_stability_check: Instant::now() - Duration::from_secs(60),
             invoice_result: String::new(),
             user,
-            qr_texture: None,
+            invoice_qr: QrView::default(),
             channel_list: Vec::new(),
             stable_channel,
             close_channel_address: String::new(),
             status_message: String::new(),
+            adjust_amount: String::new(),
+            receive_amount: String::new(),
+            receive_invoice: String::new(),
+            receive_qr: QrView::default(),
+            send_input: String::new(),
+            send_amount: String::new(),
+            send_request: None,
+            pending_stable_sends: Vec::new(),
+            lsp_offer,
+            agreement_update: None,
+            jit_amount: format!("{:.2}", config.stable_channel_defaults.expected_usd),
+            active_jit_quote: None,
+            jit_status,
+            invoice_expires_at: None,
+            deposit_address: String::new(),
+            next_deposit_check: Instant::now(),
+            closing_progress: ClosingProgress::default(),
+            last_closing_refresh: None,
+            close_channel_id: None,
+            close_confirmation: None,
+            force_close_acknowledged: false,
+            onchain_log: load_onchain_log(&config.stable_channel_dir()).unwrap_or_default(),
+            onchain_address: String::new(),
+            onchain_qr: QrView::default(),
+            onchain_send_address: String::new(),
+            onchain_send_amount: String::new(),
+            onchain_send_all: false,
+            onchain_history: Vec::new(),
+            onchain_fee_rate: None,
+            onchain_error: None,
+            last_onchain_refresh: None,
+            backup_confirmed: load_backup_confirmed(&config.stable_channel_dir()),
+            channel_backup: load_channel_backup_settings(&config.stable_channel_dir()),
+            channel_backup_due: true,
+            channel_backup_due_since: None,
+            channel_backup_job: None,
+            new_passphrase: String::new(),
+            confirm_passphrase: String::new(),
+            tray: make_tray(),
+            stabilization_paused: false,
+            quitting: false,
+            notifier,
+            high_risk_notified: false,
+            stabilizations: load_stabilizations(&config.stable_channel_dir()),
+            price_history,
+            chart_window_secs: 86_400,
+            chart_show_feeds: false,
+            backup_quiz: BackupQuiz::new(mnemonic.as_ref().map_or(12, |m| m.word_count())),
+            mnemonic,
             config,
         }
     }
@@ -211,7 +532,56 @@ impl MyApp {
                         .color(egui::Color32::GRAY),
                 );
     
-                ui.add_space(50.0);
+                ui.add_space(40.0);
+
+                ui.label(
+                    egui::RichText::new("How many dollars do you want to stabilize?")
+                        .color(egui::Color32::WHITE),
+                );
+                ui.add_space(8.0);
+                ui.add(
+                    egui::TextEdit::singleline(&mut self.jit_amount)
+                        .desired_width(120.0)
+                        .hint_text("USD"),
+                );
+                ui.add_space(8.0);
+
+                let quote = self
+                    .jit_amount
+                    .trim()
+                    .parse::<f64>()
+                    .map_err(|_| "Enter a dollar amount".to_string())
+                    .and_then(|usd| self.jit_quote(USD::from_f64(usd)));
+                let price = self.stable_channel.latest_price;
+                match &quote {
+                    Ok(quote) => {
+                        let invoice_btc = Bitcoin::from_sats(quote.invoice_msat / 1000);
+                        let fee_btc = Bitcoin::from_sats(quote.lsp_fee_msat / 1000);
+                        ui.label(format!(
+                            "You pay: {} ({})",
+                            USD::from_bitcoin(invoice_btc, price),
+                            invoice_btc
+                        ));
+                        ui.label(
+                            egui::RichText::new(format!(
+                                "Estimated LSP channel opening fee: {} ({} sats)",
+                                USD::from_bitcoin(fee_btc, price),
+                                fee_btc.sats
+                            ))
+                            .color(egui::Color32::GRAY),
+                        );
+                        ui.label(
+                            egui::RichText::new(format!("BTC/USD: ${:.2}", price))
+                                .size(12.0)
+                                .color(egui::Color32::GRAY),
+                        );
+                    }
+                    Err(e) => {
+                        ui.label(egui::RichText::new(e).color(egui::Color32::LIGHT_RED));
+                    }
+                }
+
+                ui.add_space(30.0);
     
                 // Create channel button
                 let subtle_orange = egui::Color32::from_rgba_premultiplied(247, 147, 26, 200); 
@@ -225,136 +595,369 @@ impl MyApp {
                 .fill(subtle_orange)
                 .rounding(8.0);
     
-                if ui.add(create_channel_button).clicked() {
-                    self.get_jit_invoice(ctx);
-                    self.state = AppState::WaitingForPayment;
+                if ui.add_enabled(quote.is_ok(), create_channel_button).clicked() {
+                    if let Ok(quote) = quote {
+                        self.get_jit_invoice(&quote);
+                    }
+                }
+
+                if !self.status_message.is_empty() {
+                    ui.add_space(10.0);
+                    ui.label(self.status_message.clone());
                 }
             });
         });
     }
 
     fn show_waiting_for_payment_screen(&mut self, ctx: &egui::Context) {
+        let status = self.jit_status.unwrap_or(JitStatus::AwaitingPayment);
+        let time_left = self
+            .invoice_expires_at
+            .map(|expires_at| expires_at.saturating_duration_since(Instant::now()));
+
+        if status == JitStatus::AwaitingPayment && time_left == Some(Duration::ZERO) {
+            self.regenerate_jit_invoice();
+        }
+        // Keep the countdown ticking even without user input.
+        ctx.request_repaint_after(Duration::from_secs(1));
+
         egui::CentralPanel::default().show(ctx, |ui| {
             ui.add_space(10.0);
 
             ui.vertical_centered(|ui| {
-                ui.heading(
-                    egui::RichText::new("Send yourself bitcoin to stabilize.")
-                        .size(16.0)
-                        .strong()
-                        .color(egui::Color32::WHITE),
-                );
-                ui.add_space(3.0);
-                ui.label("This is a Bolt11 Lightning invoice.");
+                match status {
+                    JitStatus::AwaitingPayment => {
+                        ui.heading(
+                            egui::RichText::new("Send yourself bitcoin to stabilize.")
+                                .size(16.0)
+                                .strong()
+                                .color(egui::Color32::WHITE),
+                        );
+                        ui.add_space(3.0);
+                        ui.label("Pay over Lightning, or on-chain from an exchange.");
+                        if let Some(time_left) = time_left {
+                            let secs = time_left.as_secs();
+                            ui.label(
+                                egui::RichText::new(format!(
+                                    "Expires in {}:{:02}. A new invoice is created when it expires.",
+                                    secs / 60,
+                                    secs % 60
+                                ))
+                                .size(12.0)
+                                .color(egui::Color32::GRAY),
+                            );
+                        }
+                    }
+                    JitStatus::DepositDetected => {
+                        ui.heading(
+                            egui::RichText::new("On-chain deposit detected")
+                                .size(16.0)
+                                .strong()
+                                .color(egui::Color32::WHITE),
+                        );
+                        ui.add_space(8.0);
+                        ui.spinner();
+                        ui.label("Your channel opens once the deposit confirms.");
+                    }
+                    JitStatus::OpeningChannel => {
+                        ui.heading(
+                            egui::RichText::new("Payment detected, opening channel...")
+                                .size(16.0)
+                                .strong()
+                                .color(egui::Color32::WHITE),
+                        );
+                        ui.add_space(8.0);
+                        ui.spinner();
+                        ui.label("Your LSP is opening a channel to forward the payment.");
+                    }
+                    JitStatus::ReceivingPayment => {
+                        ui.heading(
+                            egui::RichText::new("Channel open, receiving payment...")
+                                .size(16.0)
+                                .strong()
+                                .color(egui::Color32::WHITE),
+                        );
+                        ui.add_space(8.0);
+                        ui.spinner();
+                        ui.label("Waiting for the payment to settle in your new channel.");
+                    }
+                }
                 ui.add_space(8.0);
 
-                if let Some(ref qr) = self.qr_texture {
-                    ui.image(qr);
-                } else {
-                    ui.label("Lightning QR Missing");
-                }
+                if status == JitStatus::AwaitingPayment {
+                    self.invoice_qr.show(ui, 256.0);
 
-                ui.add_space(8.0);
+                    ui.add_space(8.0);
 
+                    ui.add(
+                        egui::TextEdit::multiline(&mut self.invoice_result)
+                            .frame(true)
+                            .desired_width(400.0)
+                            .desired_rows(3)
+                            .hint_text("Invoice..."),
+                    );
 
-                ui.add(
-                    egui::TextEdit::multiline(&mut self.invoice_result)
-                        .frame(true)
-                        .desired_width(400.0)
-                        .desired_rows(3)
-                        .hint_text("Invoice..."),
-                );
+                    ui.add_space(8.0);
 
-                ui.add_space(8.0);
+                    if ui.add(
+                        egui::Button::new(
+                            egui::RichText::new("Copy Invoice")
+                                .color(egui::Color32::BLACK)
+                                .size(16.0), 
+                        )
+                        .min_size(egui::vec2(120.0, 36.0))
+                        .fill(egui::Color32::from_gray(220))
+                        .rounding(6.0),
+                    ).clicked() {
+                        ctx.output_mut(|o| {
+                            o.copied_text = self.invoice_result.clone();
+                        });
+                    }
 
-                if ui.add(
-                    egui::Button::new(
-                        egui::RichText::new("Copy Invoice")
-                            .color(egui::Color32::BLACK)
-                            .size(16.0), 
-                    )
-                    .min_size(egui::vec2(120.0, 36.0))
-                    .fill(egui::Color32::from_gray(220))
-                    .rounding(6.0),
-                ).clicked() {
-                    ctx.output_mut(|o| {
-                        o.copied_text = self.invoice_result.clone();
-                    });
+                    ui.add_space(5.0);
+
+                    if !self.deposit_address.is_empty() && ui.add(
+                        egui::Button::new(
+                            egui::RichText::new("Copy Address")
+                                .color(egui::Color32::BLACK)
+                                .size(16.0),
+                        )
+                        .min_size(egui::vec2(120.0, 36.0))
+                        .fill(egui::Color32::from_gray(220))
+                        .rounding(6.0),
+                    ).clicked() {
+                        ctx.output_mut(|o| {
+                            o.copied_text = self.deposit_address.clone();
+                        });
+                    }
+
+                    ui.add_space(5.0);
+
+                    if ui.add(
+                        egui::Button::new(
+                            egui::RichText::new("Save QR")
+                                .color(egui::Color32::BLACK)
+                                .size(16.0),
+                        )
+                        .min_size(egui::vec2(120.0, 36.0))
+                        .fill(egui::Color32::from_gray(220))
+                        .rounding(6.0),
+                    ).clicked() {
+                        let data = self.invoice_qr.data().to_string();
+                        self.save_qr(&data, "stable-channel-invoice");
+                    }
+                    
+                    ui.add_space(5.0); 
+                    
+                    if ui.add(
+                        egui::Button::new(
+                            egui::RichText::new("Back")
+                                .color(egui::Color32::BLACK)
+                                .size(16.0),
+                        )
+                        .min_size(egui::vec2(120.0, 36.0))
+                        .fill(egui::Color32::from_gray(220))
+                        .rounding(6.0),
+                    ).clicked() {
+                        self.cancel_jit_invoice();
+                    }
                 }
-                
-                ui.add_space(5.0); 
-                
-                if ui.add(
-                    egui::Button::new(
-                        egui::RichText::new("Back")
-                            .color(egui::Color32::BLACK)
-                            .size(16.0), 
-                    )
-                    .min_size(egui::vec2(120.0, 36.0))
-                    .fill(egui::Color32::from_gray(220))
-                    .rounding(6.0), 
-                ).clicked() {
-                    self.state = AppState::OnboardingScreen;
+
+                if !self.status_message.is_empty() {
+                    ui.add_space(8.0);
+                    ui.label(self.status_message.clone());
                 }
-                
-                ui.add_space(8.0); 
+
+                ui.add_space(8.0);
             });
         });
     }
 
-    fn get_jit_invoice(&mut self, ctx: &egui::Context) {
+    /// Opens the channel once the deposit address has received enough. Only
+    /// what was paid to that address goes in, up to the quoted amount, so
+    /// other on-chain funds are left alone.
+    fn check_onchain_deposit(&mut self) {
+        let Some(quote) = self.active_jit_quote else {
+            return;
+        };
+        if self.deposit_address.is_empty() || Instant::now() < self.next_deposit_check {
+            return;
+        }
+        self.next_deposit_check = Instant::now() + DEPOSIT_CHECK_INTERVAL;
+
+        let esplora = Esplora::new(self.config.node.http_agent(), &self.config.node.chain_source_url);
+        let (confirmed_sats, unconfirmed_sats) = match received_at(&esplora, &self.deposit_address) {
+            Ok(received) => received,
+            Err(e) => {
+                eprintln!("Failed to check the deposit address: {}", e);
+                return;
+            }
+        };
+        // The channel has to hold at least the stable amount at today's price.
+        let required_sats = quote.stable_usd.to_msats(self.stable_channel.latest_price) / 1000;
+        let channel_sats = confirmed_sats
+            .min(quote.deposit_sats().max(required_sats + OPEN_CHANNEL_FEE_RESERVE_SATS))
+            .saturating_sub(OPEN_CHANNEL_FEE_RESERVE_SATS);
+        // The wallet has to have synced the deposit before it can fund the channel.
+        let spendable_sats = self.user.list_balances().spendable_onchain_balance_sats;
+
+        if channel_sats >= required_sats && spendable_sats >= channel_sats + OPEN_CHANNEL_FEE_RESERVE_SATS {
+            let lsp_address = match SocketAddress::from_str(&self.config.lsp.address) {
+                Ok(address) => address,
+                Err(e) => {
+                    self.status_message = format!("Invalid LSP address: {:?}", e);
+                    return;
+                }
+            };
+            match self.user.open_channel(
+                self.stable_channel.counterparty,
+                lsp_address,
+                channel_sats,
+                None,
+                None,
+            ) {
+                Ok(_) => {
+                    self.jit_status = Some(JitStatus::OpeningChannel);
+                    self.status_message.clear();
+                }
+                Err(e) => {
+                    self.status_message = format!("Failed to open channel, retrying shortly: {}", e);
+                    self.next_deposit_check = Instant::now() + DEPOSIT_RETRY_DELAY;
+                }
+            }
+        } else if confirmed_sats + unconfirmed_sats > 0 && self.jit_status == Some(JitStatus::AwaitingPayment) {
+            self.jit_status = Some(JitStatus::DepositDetected);
+        } else if confirmed_sats > 0 && channel_sats < required_sats {
+            self.status_message = format!(
+                "The deposit of {} sats is short of the {} sats needed at the current price",
+                confirmed_sats,
+                required_sats + OPEN_CHANNEL_FEE_RESERVE_SATS
+            );
+        }
+    }
+
+    fn regenerate_jit_invoice(&mut self) {
+        let Some(previous) = self.active_jit_quote else {
+            return;
+        };
+        // Requote at the current price so the new invoice still stabilizes the same dollars.
+        match self.jit_quote(previous.stable_usd) {
+            Ok(quote) => self.get_jit_invoice(&quote),
+            Err(e) => {
+                self.status_message = e;
+                self.cancel_jit_invoice();
+            }
+        }
+    }
+
+    /// Drops the pending JIT invoice. LDK has no way to revoke it, but once
+    /// we stop tracking it any late payment is treated as native bitcoin.
+    fn cancel_jit_invoice(&mut self) {
+        self.invoice_result.clear();
+        self.invoice_qr.clear();
+        self.active_jit_quote = None;
+        self.jit_status = None;
+        self.invoice_expires_at = None;
+        self.deposit_address.clear();
+        self.set_lifecycle(ChannelLifecycle::NoChannel);
+    }
+
+    fn jit_quote(&self, stable_usd: USD) -> Result<JitQuote, String> {
+        let defaults = &self.config.stable_channel_defaults;
+        let price = self.stable_channel.latest_price;
+
+        if price <= 0.0 {
+            return Err("Bitcoin price unavailable, try again shortly".to_string());
+        }
+        if stable_usd.0 < defaults.min_usd || stable_usd.0 > defaults.max_usd {
+            return Err(format!(
+                "Amount must be between {} and {}",
+                USD::from_f64(defaults.min_usd),
+                USD::from_f64(defaults.max_usd)
+            ));
+        }
+
+        // The LSP takes its fee out of the payment, so add it on top of the
+        // amount to stabilize. The fee depends on the payment size, hence twice.
+        let stable_msat = stable_usd.to_msats(price);
+        let lsp_fee_msat = self.config.lsp.opening_fee_msat(stable_msat);
+        let lsp_fee_msat = self.config.lsp.opening_fee_msat(stable_msat + lsp_fee_msat);
+
+        if lsp_fee_msat > defaults.max_lsp_fee_msat {
+            return Err(format!(
+                "LSP fee of {} sats is above the {} sat limit",
+                lsp_fee_msat / 1000,
+                defaults.max_lsp_fee_msat / 1000
+            ));
+        }
+
+        Ok(JitQuote {
+            stable_usd,
+            invoice_msat: stable_msat + lsp_fee_msat,
+            lsp_fee_msat,
+        })
+    }
+
+    fn get_jit_invoice(&mut self, quote: &JitQuote) {
         let description = "Stable Channel JIT payment";
     
         let result = self.user.bolt11_payment().receive_via_jit_channel(
-            20_779_000,
+            quote.invoice_msat,
             description,
-            3600,
-            Some(10_000_000),
+            self.config.stable_channel_defaults.invoice_expiry_secs,
+            Some(self.config.stable_channel_defaults.max_lsp_fee_msat),
         );
     
         match result {
             Ok(invoice) => {
                 self.invoice_result = invoice.to_string();
-                let code = QrCode::new(&self.invoice_result).unwrap();
-                let bits = code.to_colors();
-                let width = code.width();
-                let scale_factor = 4;
-                let mut imgbuf =
-                    GrayImage::new((width * scale_factor) as u32, (width * scale_factor) as u32);
-    
-                for y in 0..width {
-                    for x in 0..width {
-                        let color = if bits[y * width + x] == Color::Dark {
-                            0
-                        } else {
-                            255
-                        };
-                        for dy in 0..scale_factor {
-                            for dx in 0..scale_factor {
-                                imgbuf.put_pixel(
-                                    (x * scale_factor + dx) as u32,
-                                    (y * scale_factor + dy) as u32,
-                                    Luma([color]),
-                                );
-                            }
+                self.invoice_qr.set(&self.invoice_result);
+
+                // Offer an on-chain fallback for wallets and exchanges without Lightning.
+                // Reuse the address across regenerated invoices so late deposits still land.
+                if self.deposit_address.is_empty() {
+                    match self.user.onchain_payment().new_address() {
+                        Ok(address) => {
+                            self.deposit_address = address.to_string();
+                            self.onchain_log.record_address(&self.deposit_address);
+                            self.save_onchain_log();
                         }
+                        Err(e) => eprintln!("Failed to get deposit address: {}", e),
                     }
                 }
-                let (w, h) = (imgbuf.width() as usize, imgbuf.height() as usize);
-                let mut rgba = Vec::with_capacity(w * h * 4);
-                for pixel in imgbuf.pixels() {
-                    let lum = pixel[0];
-                    rgba.push(lum);
-                    rgba.push(lum);
-                    rgba.push(lum);
-                    rgba.push(255);
+                if let Ok(address) = Address::from_str(&self.deposit_address) {
+                    // Wallets that can't pay the offer yet, since there's no
+                    // channel to route it through, fall back to the invoice.
+                    let offer = match self.user.bolt12_payment().receive(quote.invoice_msat, description, None, None) {
+                        Ok(offer) => Some(offer.to_string()),
+                        Err(e) => {
+                            eprintln!("Failed to create offer: {}", e);
+                            None
+                        }
+                    };
+                    let uri = bip21_uri(
+                        &address.assume_checked(),
+                        quote.deposit_sats(),
+                        Some(&self.invoice_result),
+                        offer.as_deref(),
+                    );
+                    self.invoice_qr.set(&uri);
+                }
+
+                self.active_jit_quote = Some(*quote);
+                self.jit_status = Some(JitStatus::AwaitingPayment);
+                self.invoice_expires_at = Some(
+                    Instant::now()
+                        + Duration::from_secs(self.config.stable_channel_defaults.invoice_expiry_secs as u64),
+                );
+                if let PegTarget::FixedUsd(_) = self.stable_channel.peg_target {
+                    self.stable_channel.peg_target = PegTarget::FixedUsd(quote.stable_usd);
                 }
-                let color_image = egui::ColorImage::from_rgba_unmultiplied([w, h], &rgba);
-                self.qr_texture =
-                    Some(ctx.load_texture("qr_code", color_image, TextureOptions::LINEAR));
+                self.status_message.clear();
+                self.set_lifecycle(ChannelLifecycle::JitPending);
             }
             Err(e) => {
-                self.invoice_result = format!("Error: {e:?}");
+                self.status_message = format!("Failed to get invoice: {e:?}");
             }
         }
     }
@@ -369,19 +972,77 @@ impl MyApp {
                         let balances = self.user.list_balances();
                         let lightning_balance_btc = Bitcoin::from_sats(balances.total_lightning_balance_sats);
                         let lightning_balance_usd = USD::from_bitcoin(lightning_balance_btc, self.stable_channel.latest_price);
+                        let native_btc = self.stable_channel.native_btc;
+                        let native_usd = USD::from_bitcoin(native_btc, self.stable_channel.latest_price);
+                        let stable_usd = lightning_balance_usd - native_usd;
+                        let stable_btc = lightning_balance_btc - native_btc;
               
                         ui.add_space(30.0);
 
+                        if !self.backup_confirmed {
+                            ui.group(|ui| {
+                                ui.label(
+                                    egui::RichText::new("Your wallet isn't backed up")
+                                        .strong()
+                                        .color(Color32::from_rgb(255, 200, 0)),
+                                );
+                                ui.label("If you lose this device you lose your funds.");
+                                if ui.button("Back up now").clicked() {
+                                    self.status_message.clear();
+                                    self.state = AppState::BackupScreen;
+                                }
+                            });
+                            ui.add_space(20.0);
+                        }
+
+                        self.show_recovery_panel(ui);
+
                         ui.group(|ui| {
                             ui.add_space(20.0);
                             ui.heading("Your Stable Balance");
                             ui.add(egui::Label::new(
-                                egui::RichText::new(lightning_balance_usd.to_string())
+                                egui::RichText::new(stable_usd.to_string())
                                     .size(36.0)
                                     .strong(),
                             ));
                             ui.label(format!("Agreed Peg USD: {}", self.stable_channel.expected_usd));
-                            ui.label(format!("Bitcoin: {}", lightning_balance_btc.to_string()));
+                            ui.label(format!("Bitcoin: {}", stable_btc));
+                            ui.add_space(20.0);
+                        });
+
+                        ui.add_space(10.0);
+
+                        ui.horizontal(|ui| {
+                            ui.add_space((ui.available_width() - 250.0).max(0.0) / 2.0);
+                            if ui.add(egui::Button::new("Send").min_size(egui::vec2(120.0, 36.0)).rounding(6.0)).clicked() {
+                                self.status_message.clear();
+                                self.state = AppState::SendScreen;
+                            }
+                            if ui.add(egui::Button::new("Receive").min_size(egui::vec2(120.0, 36.0)).rounding(6.0)).clicked() {
+                                self.status_message.clear();
+                                self.state = AppState::ReceiveScreen;
+                            }
+                        });
+                        if ui.link("On-chain wallet").clicked() {
+                            self.open_onchain_screen();
+                        }
+
+                        ui.add_space(20.0);
+
+                        ui.group(|ui| {
+                            ui.add_space(20.0);
+                            ui.heading("Native Bitcoin");
+                            ui.add(egui::Label::new(
+                                egui::RichText::new(native_btc.to_string())
+                                    .size(24.0)
+                                    .strong(),
+                            ));
+                            ui.label(format!("Value: {}", native_usd));
+                            ui.label(
+                                egui::RichText::new("Not stabilized, moves with the bitcoin price.")
+                                    .size(12.0)
+                                    .color(Color32::GRAY),
+                            );
                             ui.add_space(20.0);
                         });
 
@@ -391,6 +1052,17 @@ impl MyApp {
                             ui.add_space(20.0);
                             ui.heading("Bitcoin Price");
                             ui.label(format!("${:.2}", self.stable_channel.latest_price));
+                            if self.stable_channel.pricing_mode != PricingMode::Spot {
+                                ui.label(
+                                    egui::RichText::new(format!(
+                                        "{} · spot ${:.2}",
+                                        self.stable_channel.pricing_mode.label(),
+                                        self.stable_channel.spot_price
+                                    ))
+                                    .size(12.0)
+                                    .color(Color32::GRAY),
+                                );
+                            }
                             ui.add_space(20.0);
 
                             let last_updated = self.last_stability_check.elapsed().as_secs();
@@ -400,6 +1072,19 @@ impl MyApp {
                                     .size(12.0)
                                     .color(Color32::GRAY),
                             );
+                            if ui.link("Price history").clicked() {
+                                self.state = AppState::ChartScreen;
+                            }
+                            self.show_price_sources(ui);
+                            if self.stabilization_paused {
+                                ui.horizontal(|ui| {
+                                    ui.label(egui::RichText::new("Stabilization paused").color(Color32::from_rgb(255, 200, 0)));
+                                    if ui.small_button("Resume").clicked() {
+                                        self.stabilization_paused = false;
+                                        self.update_tray();
+                                    }
+                                });
+                            }
                         });
 
                         ui.add_space(20.0);
@@ -486,7 +1171,45 @@ impl MyApp {
         
                             // ui.add_space(30.0);
         
+                            ui.collapsing("Adjust Stable Balance", |ui| {
+                                self.show_adjust_stable_balance(ui);
+                            });
+
+                            ui.add_space(10.0);
+
+                            ui.collapsing("Channel Backup", |ui| {
+                                self.show_channel_backup(ui);
+                            });
+
+                            ui.add_space(10.0);
+
+                            ui.collapsing("Encryption", |ui| {
+                                self.show_encryption(ui);
+                            });
+
+                            ui.add_space(10.0);
+
                             ui.collapsing("Close Channel", |ui| {
+                                let channels = self.user.list_channels();
+                                if self.close_channel_id.is_none() {
+                                    self.close_channel_id = Some(self.stable_channel.channel_id);
+                                }
+                                if channels.len() > 1 {
+                                    ui.label("Channel to close:");
+                                    for channel in &channels {
+                                        ui.radio_value(
+                                            &mut self.close_channel_id,
+                                            Some(channel.channel_id),
+                                            format!(
+                                                "{} ({})",
+                                                channel.channel_id,
+                                                Bitcoin::from_sats(channel.outbound_capacity_msat / 1000)
+                                            ),
+                                        );
+                                    }
+                                    ui.add_space(10.0);
+                                }
+
                                 ui.label("Withdrawal address (minus transaction fees):");
                                 ui.add_space(10.0);
                                 ui.text_edit_singleline(&mut self.close_channel_address);
@@ -502,7 +1225,7 @@ impl MyApp {
                                 )
                                 .clicked()
                                 {
-                                    close_channels_to_address(&self.user, self.close_channel_address.clone());
+                                    self.prepare_close(&channels);
                                 }
                             });
 
@@ -515,80 +1238,1597 @@ impl MyApp {
                     });
                 });
         });
+
+        self.show_close_confirmation(ctx);
     }
 
-    fn show_closing_screen(&mut self, ctx: &egui::Context) {
-        egui::TopBottomPanel::top("top_panel").show(ctx, |ui| {
-            ui.horizontal_centered(|ui| {
-                ui.heading(
-                    egui::RichText::new(format!("Withdrawal processing")).size(28.0).strong(),
-                );    
-            });
-    
-            ui.add_space(20.0);
-            ui.horizontal_centered(|ui| {
-                ui.heading(                    
-                    egui::RichText::new(format!("{}",self.close_channel_address)).size(28.0).strong(), 
-                );
-            });
+    /// Validates the close request and asks the user to confirm it.
+    fn prepare_close(&mut self, channels: &[ChannelDetails]) {
+        let address = match validate_withdrawal_address(&self.close_channel_address, self.config.node.network()) {
+            Ok(address) => address,
+            Err(e) => {
+                self.status_message = e;
+                return;
+            }
+        };
+        let Some(channel) = channels
+            .iter()
+            .find(|c| Some(c.channel_id) == self.close_channel_id)
+        else {
+            self.status_message = "Select a channel to close".to_string();
+            return;
+        };
+
+        let (closing_fee_sats, sweep_fee_sats) = estimate_close_fees(channel);
+        self.close_confirmation = Some(CloseConfirmation {
+            channel: channel.clone(),
+            address: address.to_string(),
+            closing_fee_sats,
+            sweep_fee_sats,
         });
     }
 
-    fn poll_for_events(&mut self) {
-        while let Some(event) = self.user.next_event() {
-            match event {
-                Event::ChannelReady { .. } => {
-                    check_stability(&self.user, &mut self.stable_channel);
-                    self.state = AppState::MainScreen;
-                }
-                
-                Event::PaymentReceived { .. } => {
-                    self.state = AppState::MainScreen;
-                    println!("payment received");
-                }
+    fn show_close_confirmation(&mut self, ctx: &egui::Context) {
+        let Some(confirmation) = &self.close_confirmation else {
+            return;
+        };
 
-                Event::ChannelClosed { .. } => {
-                    self.state = AppState::ClosingScreen;
-                    println!("channel closed");
+        let price = self.stable_channel.latest_price;
+        let balance = Bitcoin::from_sats(confirmation.channel.outbound_capacity_msat / 1000);
+        let fees = Bitcoin::from_sats(confirmation.closing_fee_sats + confirmation.sweep_fee_sats);
+        let mut confirmed = false;
+        let mut cancelled = false;
 
+        egui::Window::new("Close channel?")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, egui::vec2(0.0, 0.0))
+            .show(ctx, |ui| {
+                ui.label(format!("Channel: {}", confirmation.channel.channel_id));
+                ui.label(format!("Balance: {} ({})", balance, USD::from_bitcoin(balance, price)));
+                ui.label(format!("Withdraw to: {}", confirmation.address));
+                ui.add_space(10.0);
+                ui.label(format!(
+                    "Estimated fees: {} sats to close, {} sats to sweep ({})",
+                    confirmation.closing_fee_sats,
+                    confirmation.sweep_fee_sats,
+                    USD::from_bitcoin(fees, price)
+                ));
+                let note = if confirmation.channel.channel_id == self.stable_channel.channel_id {
+                    "Your funds stop being stabilized once the channel closes. \
+                     They are swept to your address as soon as they are spendable on-chain."
+                } else {
+                    "The channel's funds go to your on-chain wallet once spendable. \
+                     Send them to your address from there."
+                };
+                ui.label(egui::RichText::new(note).size(12.0).color(Color32::GRAY));
+                ui.add_space(10.0);
+                ui.horizontal(|ui| {
+                    confirmed = ui.button("Close Channel").clicked();
+                    cancelled = ui.button("Cancel").clicked();
+                });
+            });
 
-                }
-                _ => {
-                
-                }
+        if cancelled {
+            self.close_confirmation = None;
+        } else if confirmed {
+            if let Some(confirmation) = self.close_confirmation.take() {
+                self.confirm_close(confirmation);
             }
-            self.user.event_handled();
         }
     }
 
-    pub fn connect_to_lsp_and_entry_node(&mut self) {
-        let _connected_to_lsp = self.user.connect(
-            PublicKey::from_str("0367631f3a8ca46bccf6d8eae8b728963337f8a6825199386c9a48987ea82b54cd")
-                .unwrap(),
-            SocketAddress::from_str("127.0.0.1:9737").unwrap(),
-            true,
-        );
-    
-        println!("Connection result: {:?}", _connected_to_lsp.unwrap());
+    fn confirm_close(&mut self, confirmation: CloseConfirmation) {
+        if let Err(e) = close_channel(&self.user, &confirmation.channel) {
+            self.status_message = e;
+            return;
+        }
 
-        let _connected_to_exchange = self.user.connect(
-            PublicKey::from_str("03e9d73c317a6113a30e85d7dafcebaa509c1744e0528d392ae975d2e4177d11dc")
-                .unwrap(),
-            SocketAddress::from_str("127.0.0.1:9735").unwrap(),
-            true,
-        );
-    
-        println!("Connection result: {:?}", _connected_to_exchange.unwrap());
-    
+        if confirmation.channel.channel_id == self.stable_channel.channel_id {
+            self.stable_channel.withdrawal_address = Some(confirmation.address);
+            self.stable_channel.sweep_txid = None;
+            self.stable_channel.onchain_before_close_sats =
+                Some(self.user.list_balances().spendable_onchain_balance_sats);
+            self.save_stable_channel();
+            self.set_lifecycle(ChannelLifecycle::Closing);
+        } else {
+            // Only the stable channel is swept; other channels' funds stay in the wallet.
+            self.status_message =
+                "Channel closing. Its funds go to your on-chain wallet once spendable, send them on from there."
+                    .to_string();
+        }
+    }
 
-        let node_info = self.user
-            .network_graph()
-            .node(&NodeId::from_pubkey(
-                &PublicKey::from_str("03ebdb4d14e3101c1d63e3d5555db2d15bc50d32bc30919b7dfd3d35609b978ff4")
-                    .unwrap(),
-            ));
-    
-        println!("Node information: {:?}", node_info);
+    /// Sweeps the closed channel's funds to the withdrawal address once LDK
+    /// has claimed everything and it is spendable in the on-chain wallet.
+    fn try_deferred_sweep(&mut self) {
+        if self.stable_channel.sweep_txid.is_some() {
+            return;
+        }
+        let Some(address) = self.stable_channel.withdrawal_address.clone() else {
+            return;
+        };
+        if !matches!(
+            self.lifecycle,
+            ChannelLifecycle::ClosedSweepPending | ChannelLifecycle::Swept
+        ) {
+            return;
+        }
+
+        let balances = self.user.list_balances();
+        let before_close_sats = self.stable_channel.onchain_before_close_sats.unwrap_or(0);
+        let claimed_sats = balances.spendable_onchain_balance_sats.saturating_sub(before_close_sats);
+        if claims_pending(&balances, self.stable_channel.channel_id) || claimed_sats == 0 {
+            return;
+        }
+
+        // Leave whatever was in the wallet before the close where it is.
+        let amount_sats = (before_close_sats > 0).then_some(claimed_sats);
+        let result = validate_withdrawal_address(&address, self.config.node.network())
+            .and_then(|address| sweep_to_address(&self.user, &address, amount_sats));
+        match result {
+            Ok(txid) => {
+                println!("Swept closed channel funds in {}", txid);
+                self.onchain_log.record_send(&txid.to_string(), &address, amount_sats);
+                self.save_onchain_log();
+                self.stable_channel.sweep_txid = Some(txid.to_string());
+                self.save_stable_channel();
+                self.last_closing_refresh = None;
+            }
+            Err(e) => self.status_message = e,
+        }
+    }
+
+    fn show_closing_screen(&mut self, ctx: &egui::Context) {
+        let refresh_due = self
+            .last_closing_refresh
+            .map_or(true, |last| last.elapsed() >= Duration::from_secs(30));
+        if refresh_due {
+            let esplora = Esplora::new(self.config.node.http_agent(), &self.config.node.chain_source_url);
+            self.closing_progress = ClosingProgress::fetch(
+                &esplora,
+                self.stable_channel.funding_txo,
+                self.stable_channel.sweep_txid.as_deref(),
+            );
+            self.last_closing_refresh = Some(Instant::now());
+        }
+        ctx.request_repaint_after(Duration::from_secs(30));
+
+        let tip_height = self.closing_progress.tip_height.max(self.user.status().current_best_block.height);
+        let balances = self.user.list_balances();
+        let lines = closing_balances(&balances, tip_height);
+        // Only the stable channel is being closed here. LDK lists it until the
+        // closing transaction is broadcast.
+        let channels: Vec<ChannelDetails> = self
+            .user
+            .list_channels()
+            .into_iter()
+            .filter(|c| c.channel_id == self.stable_channel.channel_id)
+            .collect();
+
+        egui::CentralPanel::default().show(ctx, |ui| {
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                ui.vertical_centered(|ui| {
+                    ui.add_space(20.0);
+                    let heading = match self.lifecycle {
+                        ChannelLifecycle::Closing => "Closing your channel",
+                        ChannelLifecycle::ClosedSweepPending => "Withdrawal processing",
+                        _ => "Withdrawal complete",
+                    };
+                    ui.heading(egui::RichText::new(heading).size(28.0).strong());
+                    ui.add_space(10.0);
+                    if let Some(address) = &self.stable_channel.withdrawal_address {
+                        ui.label(format!("To: {}", address));
+                    }
+                    ui.add_space(20.0);
+
+                    for channel in &channels {
+                        ui.group(|ui| {
+                            ui.label(egui::RichText::new(format!("Channel {}", channel.channel_id)).strong());
+                            ui.label("Waiting for the channel partner to agree to close.");
+                            ui.label(format!("Balance: {}", Bitcoin::from_sats(channel.outbound_capacity_msat / 1000)));
+                        });
+                        ui.add_space(10.0);
+                    }
+                    self.show_recovery_panel(ui);
+                    if !self.status_message.is_empty() {
+                        ui.label(self.status_message.clone());
+                        ui.add_space(10.0);
+                    }
+
+                    ui.group(|ui| {
+                        ui.label(egui::RichText::new("Closing transaction").strong());
+                        match &self.closing_progress.closing_tx {
+                            Some(tx) => {
+                                ui.label(&tx.txid);
+                                ui.label(format!("{} confirmations", tx.confirmations(tip_height)));
+                            }
+                            None if self.stable_channel.funding_txo.is_some() => {
+                                ui.label("Not yet broadcast");
+                            }
+                            None => {
+                                ui.label("Unknown, the channel's funding output was never recorded");
+                            }
+                        }
+                    });
+                    ui.add_space(10.0);
+
+                    if !lines.is_empty() {
+                        ui.group(|ui| {
+                            ui.label(egui::RichText::new("Funds being claimed").strong());
+                            for line in &lines {
+                                ui.add_space(5.0);
+                                if let Some(channel_id) = line.channel_id {
+                                    ui.label(
+                                        egui::RichText::new(format!("Channel {}", channel_id))
+                                            .size(12.0)
+                                            .color(Color32::GRAY),
+                                    );
+                                }
+                                ui.label(format!("{}: {}", Bitcoin::from_sats(line.amount_sats), line.status));
+                            }
+                        });
+                        ui.add_space(10.0);
+                    }
+
+                    ui.group(|ui| {
+                        ui.label(egui::RichText::new("Sweep to your address").strong());
+                        match &self.closing_progress.sweep_tx {
+                            Some(tx) => {
+                                ui.label(&tx.txid);
+                                ui.label(format!("{} confirmations", tx.confirmations(tip_height)));
+                            }
+                            None => {
+                                ui.label("Waiting for the channel funds to become spendable");
+                            }
+                        }
+                    });
+
+                    if let Some(error) = &self.closing_progress.error {
+                        ui.add_space(10.0);
+                        ui.label(egui::RichText::new(error).color(Color32::LIGHT_RED));
+                    }
+
+                    let sweep_confirmed = self
+                        .closing_progress
+                        .sweep_tx
+                        .as_ref()
+                        .map_or(true, |tx| tx.confirmations(tip_height) > 0);
+                    if self.lifecycle == ChannelLifecycle::Swept && sweep_confirmed {
+                        ui.add_space(20.0);
+                        ui.label("All funds are back on-chain.");
+                        ui.add_space(10.0);
+                        if ui
+                            .add(egui::Button::new("Start over").min_size(egui::vec2(120.0, 36.0)).rounding(6.0))
+                            .clicked()
+                        {
+                            self.start_over();
+                        }
+                    }
+                    ui.add_space(10.0);
+                    if ui.link("On-chain wallet").clicked() {
+                        self.open_onchain_screen();
+                    }
+
+                    ui.add_space(20.0);
+                });
+            });
+        });
+    }
+
+    /// Remembers when we last had a connection to the LSP. Only time the app
+    /// has been running counts, so the clock starts at launch rather than
+    /// when the LSP was last seen before the app was closed.
+    fn track_counterparty(&mut self) {
+        if !matches!(
+            self.lifecycle,
+            ChannelLifecycle::ChannelPending | ChannelLifecycle::Ready | ChannelLifecycle::Closing
+        ) {
+            return;
+        }
+        if is_connected(&self.user, &self.stable_channel.counterparty)
+            || self.stable_channel.counterparty_last_seen.is_none()
+        {
+            self.stable_channel.counterparty_last_seen = Some(unix_now());
+        }
+    }
+
+    /// Offers to force-close the stable channel once the LSP has been offline
+    /// for longer than the configured threshold.
+    fn show_recovery_panel(&mut self, ui: &mut egui::Ui) {
+        let Some(channel) = self
+            .user
+            .list_channels()
+            .into_iter()
+            .find(|c| c.channel_id == self.stable_channel.channel_id)
+        else {
+            return;
+        };
+        let connected = is_connected(&self.user, &channel.counterparty_node_id);
+        let Some(offline) = offline_secs(connected, self.stable_channel.counterparty_last_seen, unix_now()) else {
+            return;
+        };
+        if offline < self.config.lsp.offline_threshold_secs {
+            return;
+        }
+
+        ui.group(|ui| {
+            ui.add_space(10.0);
+            ui.label(
+                egui::RichText::new(format!(
+                    "Your channel partner has been offline for {}",
+                    format_duration(offline)
+                ))
+                .strong()
+                .color(Color32::LIGHT_RED),
+            );
+            ui.add_space(5.0);
+            ui.label("You can wait for it to come back, or force-close the channel to recover your funds.");
+            ui.add_space(5.0);
+            ui.label(
+                egui::RichText::new(force_close_explanation(&channel))
+                    .size(12.0)
+                    .color(Color32::GRAY),
+            );
+            ui.add_space(10.0);
+
+            ui.label("Withdrawal address:");
+            ui.text_edit_singleline(&mut self.close_channel_address);
+            ui.checkbox(
+                &mut self.force_close_acknowledged,
+                format!(
+                    "I understand my funds will be locked for {} blocks",
+                    spend_delay_blocks(&channel)
+                ),
+            );
+            ui.add_space(5.0);
+            if ui
+                .add_enabled(self.force_close_acknowledged, egui::Button::new("Force Close"))
+                .clicked()
+            {
+                self.force_close(&channel);
+            }
+            ui.add_space(10.0);
+        });
+        ui.add_space(20.0);
+    }
+
+    fn force_close(&mut self, channel: &ChannelDetails) {
+        let address = match validate_withdrawal_address(&self.close_channel_address, self.config.node.network()) {
+            Ok(address) => address,
+            Err(e) => {
+                self.status_message = e;
+                return;
+            }
+        };
+        if let Err(e) = force_close_channel(&self.user, channel, "Channel partner unresponsive") {
+            self.status_message = e;
+            return;
+        }
+
+        self.force_close_acknowledged = false;
+        self.stable_channel.withdrawal_address = Some(address.to_string());
+        self.stable_channel.sweep_txid = None;
+        self.stable_channel.onchain_before_close_sats = Some(self.user.list_balances().spendable_onchain_balance_sats);
+        self.save_stable_channel();
+        self.set_lifecycle(ChannelLifecycle::Closing);
+    }
+
+    /// Forgets the closed channel so the user can onboard again.
+    fn start_over(&mut self) {
+        let defaults = &self.config.stable_channel_defaults;
+        self.stable_channel.channel_id = ChannelId::from_bytes([0; 32]);
+        self.stable_channel.peg_target = defaults.peg_target();
+        self.stable_channel.pricing_mode = defaults.pricing_mode;
+        self.stable_channel.expected_usd = USD::from_f64(defaults.expected_usd);
+        self.stable_channel.native_btc = Bitcoin::from_sats(0);
+        self.stable_channel.funding_txo = None;
+        self.stable_channel.sweep_txid = None;
+        self.stable_channel.withdrawal_address = None;
+        self.stable_channel.onchain_before_close_sats = None;
+        self.stable_channel.counterparty_last_seen = None;
+        self.save_stable_channel();
+
+        self.close_channel_address.clear();
+        self.close_channel_id = None;
+        self.closing_progress = ClosingProgress::default();
+        self.last_closing_refresh = None;
+        self.status_message.clear();
+        self.set_lifecycle(ChannelLifecycle::NoChannel);
+    }
+
+    fn poll_for_events(&mut self) {
+        while let Some(event) = self.user.next_event() {
+            match event {
+                Event::ChannelReady { channel_id, .. } => {
+                    self.stable_channel.channel_id = channel_id;
+                    refresh_price(&mut self.stable_channel);
+                    let mut funded_by_us = false;
+                    if let Some(channel) = self
+                        .user
+                        .list_channels()
+                        .into_iter()
+                        .find(|c| c.channel_id == channel_id)
+                    {
+                        funded_by_us = channel.is_outbound;
+                        update_balances(&mut self.stable_channel, Some(channel));
+                        apply_peg_target(&mut self.stable_channel);
+                    }
+                    // The counterparty learns the amounts from us.
+                    self.on_agreement_changed();
+                    self.run_stability_check();
+                    self.notifier.notify(NotificationKind::ChannelReady, "Your stable channel is ready".to_string());
+                    if funded_by_us {
+                        // Opened from an on-chain deposit, so there's no payment to wait for.
+                        self.jit_status = None;
+                        self.active_jit_quote = None;
+                        self.invoice_expires_at = None;
+                        self.deposit_address.clear();
+                        self.invoice_result.clear();
+                        self.invoice_qr.clear();
+                    } else if self.jit_status.is_some() {
+                        // The JIT payment is forwarded once the channel is ready.
+                        self.jit_status = Some(JitStatus::ReceivingPayment);
+                    }
+                    self.set_lifecycle(ChannelLifecycle::Ready);
+                }
+
+                Event::ChannelPending { .. } => {
+                    println!("channel pending");
+                    if self.jit_status.is_some() {
+                        self.jit_status = Some(JitStatus::OpeningChannel);
+                    }
+                    self.set_lifecycle(ChannelLifecycle::ChannelPending);
+                }
+                
+                Event::PaymentReceived { payment_hash, amount_msat, .. } => {
+                    println!("payment received");
+                    self.on_payment_received(payment_hash.0, amount_msat);
+                }
+
+                Event::PaymentSuccessful { payment_id: Some(payment_id), fee_paid_msat, .. } => {
+                    println!("payment sent");
+                    self.on_payment_sent(payment_id.0, fee_paid_msat);
+                }
+
+                Event::PaymentFailed { payment_id: Some(payment_id), .. } => {
+                    println!("payment failed");
+                    self.on_payment_failed(payment_id.0);
+                }
+
+                Event::ChannelClosed { channel_id, .. } => {
+                    println!("channel closed");
+                    if channel_id == self.stable_channel.channel_id {
+                        self.notifier.notify(
+                            NotificationKind::ChannelClosed,
+                            "Your stable channel has closed, funds are on their way on-chain".to_string(),
+                        );
+                        self.set_lifecycle(ChannelLifecycle::ClosedSweepPending);
+                    }
+                }
+                _ => {
+                
+                }
+            }
+            // Every event may come with a channel monitor update.
+            self.channel_backup_due = true;
+            self.user.event_handled();
+        }
+    }
+
+    fn on_payment_received(&mut self, payment_hash: [u8; 32], amount_msat: u64) {
+        refresh_price(&mut self.stable_channel);
+        if let Some(channel) = self
+            .user
+            .list_channels()
+            .into_iter()
+            .find(|c| c.channel_id == self.stable_channel.channel_id)
+        {
+            update_balances(&mut self.stable_channel, Some(channel));
+        }
+
+        if self.jit_status.is_some() {
+            if let Some(quote) = self.active_jit_quote {
+                self.check_lsp_fee(&quote, amount_msat);
+            }
+            // The onboarding payment funds the channel, so split it per the peg target.
+            apply_peg_target(&mut self.stable_channel);
+            self.on_agreement_changed();
+            self.jit_status = None;
+            self.active_jit_quote = None;
+            self.invoice_expires_at = None;
+            self.invoice_result.clear();
+            self.invoice_qr.clear();
+            self.deposit_address.clear();
+            self.state = screen_for(self.lifecycle);
+        } else if let Some(pos) = self
+            .stable_channel
+            .pending_stable_receives
+            .iter()
+            .position(|(hash, _)| *hash == payment_hash)
+        {
+            let (_, amount) = self.stable_channel.pending_stable_receives.remove(pos);
+            increase_stable_balance(&mut self.stable_channel, amount);
+            self.receive_invoice.clear();
+            self.receive_qr.clear();
+            self.status_message = format!("Received {} into your stable balance", amount);
+            self.on_agreement_changed();
+        } else if let Some(agreement) = received_agreement(&self.user, payment_hash) {
+            // The stable receiver changed what we stabilize against.
+            if agreement.apply_to(&mut self.stable_channel) {
+                println!("Counterparty updated the agreement to {}", agreement.expected_usd);
+                self.channel_backup_due = true;
+            }
+        } else if is_stabilization_payment(&self.user, payment_hash) {
+            // The counterparty topping us back up to the peg.
+            let received = USD::from_bitcoin(
+                Bitcoin::from_sats(amount_msat / 1000),
+                self.stable_channel.latest_price,
+            );
+            self.notifier.notify(
+                NotificationKind::StabilizationReceived,
+                format!("Received {} to keep your balance stable", received),
+            );
+            let record = stabilization_record(&self.stable_channel, hex::encode(payment_hash), false, received);
+            self.record_stabilization(record);
+        } else {
+            // Anything not received into the stable balance stays native bitcoin,
+            // otherwise the next stability check would pay it away.
+            let received = Bitcoin::from_sats(amount_msat / 1000);
+            self.stable_channel.native_btc = self.stable_channel.native_btc + received;
+        }
+
+        self.save_stable_channel();
+    }
+
+    /// The fee shown before paying is only an estimate from the config, so
+    /// tell the user if the LSP took more out of the payment.
+    fn check_lsp_fee(&mut self, quote: &JitQuote, received_msat: u64) {
+        let charged_msat = quote.invoice_msat.saturating_sub(received_msat);
+        if charged_msat <= quote.lsp_fee_msat + 1000 {
+            return;
+        }
+        let message = format!(
+            "The LSP charged {} sats to open the channel, {} sats more than estimated",
+            charged_msat / 1000,
+            (charged_msat - quote.lsp_fee_msat) / 1000
+        );
+        eprintln!("{}. Check the opening fee settings in config.toml.", message);
+        self.status_message = message;
+    }
+
+    fn record_stabilization(&mut self, record: StabilizationRecord) {
+        self.stabilizations.push(record);
+        if let Err(e) = save_stabilizations(&self.config.stable_channel_dir(), &self.stabilizations) {
+            eprintln!("Failed to save stabilization payments: {}", e);
+        }
+    }
+
+    fn run_stability_check(&mut self) {
+        if self.stable_channel.agreement_pending && self.lsp_offer.is_some() {
+            // Both sides have to stabilize against the same amounts and price.
+            self.send_agreement();
+            return;
+        }
+        let outcome = check_stability(&self.user, &mut self.stable_channel);
+        if self.price_history.record(&self.stable_channel, unix_now()) {
+            if let Err(e) = save_price_history(&self.config.stable_channel_dir(), &self.price_history) {
+                eprintln!("Failed to save price history: {}", e);
+            }
+        }
+        if !matches!(outcome, StabilityOutcome::HighRisk(_)) {
+            self.high_risk_notified = false;
+        }
+        match outcome {
+            StabilityOutcome::Paid(record) => {
+                self.notifier.notify(
+                    NotificationKind::StabilizationSent,
+                    format!("Sent {} to keep your balance stable", record.amount_usd),
+                );
+                self.record_stabilization(record);
+            }
+            StabilityOutcome::PaymentFailed(e) => self.notifier.notify(
+                NotificationKind::StabilizationSent,
+                format!("Stabilization payment failed: {}", e),
+            ),
+            // Only once per episode, not on every check.
+            StabilityOutcome::HighRisk(risk_level) if !self.high_risk_notified => {
+                self.high_risk_notified = true;
+                self.notifier.notify(
+                    NotificationKind::HighRisk,
+                    format!("Risk level is high ({}), stabilization is on hold", risk_level),
+                );
+            }
+            StabilityOutcome::Stable | StabilityOutcome::Waiting | StabilityOutcome::HighRisk(_) => {}
+        }
+    }
+
+    /// Moves to a new lifecycle stage, persisting it and switching screens.
+    fn set_lifecycle(&mut self, lifecycle: ChannelLifecycle) {
+        if lifecycle == self.lifecycle {
+            return;
+        }
+        println!("Channel lifecycle: {:?} -> {:?}", self.lifecycle, lifecycle);
+        self.lifecycle = lifecycle;
+        if let Err(e) = save_lifecycle(&self.config.stable_channel_dir(), lifecycle) {
+            eprintln!("Failed to save channel lifecycle: {}", e);
+        }
+
+        // Stay on the waiting screen until the JIT payment has settled.
+        if !(lifecycle == ChannelLifecycle::Ready && self.jit_status.is_some()) {
+            self.state = screen_for(lifecycle);
+        }
+    }
+
+    /// Re-derives the lifecycle stage from the node, catching transitions
+    /// that happened without an event, e.g. closing balances being swept.
+    fn refresh_lifecycle(&mut self) {
+        let lifecycle = ChannelLifecycle::derive(
+            self.lifecycle,
+            &self.user.list_channels(),
+            &self.user.list_balances(),
+            self.stable_channel.channel_id,
+        );
+        self.set_lifecycle(lifecycle);
+    }
+
+    fn save_stable_channel(&self) {
+        if let Err(e) = save_stable_channel(&self.config.stable_channel_dir(), &self.stable_channel) {
+            eprintln!("Failed to save stable channel: {}", e);
+        }
+    }
+
+    fn create_stable_invoice(&mut self, amount: USD) {
+        let amount_msat = amount.to_msats(self.stable_channel.latest_price);
+        match self.user.bolt11_payment().receive(amount_msat, "Stable Channels payment", 3600) {
+            Ok(invoice) => {
+                self.stable_channel
+                    .pending_stable_receives
+                    .push((invoice.payment_hash().to_byte_array(), amount));
+                self.save_stable_channel();
+                self.receive_invoice = invoice.to_string();
+                self.receive_qr.set(&self.receive_invoice);
+                self.status_message = format!("Pay this invoice to add {} to your stable balance", amount);
+            }
+            Err(e) => self.status_message = format!("Failed to create invoice: {}", e),
+        }
+    }
+
+    fn on_payment_sent(&mut self, payment_id: [u8; 32], fee_paid_msat: Option<u64>) {
+        if self.agreement_update.as_ref().is_some_and(|update| update.payment_id.0 == payment_id) {
+            self.on_agreement_sent();
+            return;
+        }
+        let Some(pos) = self
+            .pending_stable_sends
+            .iter()
+            .position(|(id, _)| *id == payment_id)
+        else {
+            return;
+        };
+        let (_, amount) = self.pending_stable_sends.remove(pos);
+
+        // Routing fees leave the channel too, so they come out of the stable balance.
+        let fee = USD::from_bitcoin(
+            Bitcoin::from_sats(fee_paid_msat.unwrap_or(0) / 1000),
+            self.stable_channel.latest_price,
+        );
+        decrease_stable_balance(&mut self.stable_channel, amount + fee);
+        self.status_message = format!("Sent {} (fee {})", amount, fee);
+        self.on_agreement_changed();
+    }
+
+    fn on_payment_failed(&mut self, payment_id: [u8; 32]) {
+        if self.agreement_update.as_ref().is_some_and(|update| update.payment_id.0 == payment_id) {
+            // Tried again at the next stability check.
+            if let Some(update) = self.agreement_update.take() {
+                update.failed(&mut self.stable_channel);
+            }
+            self.save_stable_channel();
+            return;
+        }
+        if let Some(pos) = self
+            .pending_stable_sends
+            .iter()
+            .position(|(id, _)| *id == payment_id)
+        {
+            let (_, amount) = self.pending_stable_sends.remove(pos);
+            self.status_message = format!("Payment of {} failed", amount);
+        }
+    }
+
+    fn show_adjust_stable_balance(&mut self, ui: &mut egui::Ui) {
+        ui.label("Amount (USD):");
+        ui.add_space(10.0);
+        ui.text_edit_singleline(&mut self.adjust_amount);
+        ui.add_space(10.0);
+
+        let amount = match self.adjust_amount.trim().parse::<f64>() {
+            Ok(amount) if amount > 0.0 => Some(USD::from_f64(amount)),
+            _ => None,
+        };
+
+        let can_change = self.lsp_offer.is_some();
+        let amount = amount.filter(|_| can_change);
+        ui.horizontal(|ui| {
+            if ui.add_enabled(amount.is_some(), egui::Button::new("Receive via Lightning").rounding(6.0)).clicked() {
+                self.receive_amount = self.adjust_amount.clone();
+                self.state = AppState::ReceiveScreen;
+            }
+
+            if ui.add_enabled(amount.is_some(), egui::Button::new("Convert from native BTC").rounding(6.0)).clicked() {
+                if let Some(amount) = amount {
+                    self.status_message = match move_to_stable(&mut self.stable_channel, amount) {
+                        Ok(()) => format!("Moved {} into your stable balance", amount),
+                        Err(e) => e,
+                    };
+                    self.on_agreement_changed();
+                }
+            }
+
+            if ui.add_enabled(amount.is_some(), egui::Button::new("Convert to native BTC").rounding(6.0)).clicked() {
+                if let Some(amount) = amount {
+                    self.status_message = match move_to_native(&mut self.stable_channel, amount) {
+                        Ok(()) => format!("Moved {} to native bitcoin", amount),
+                        Err(e) => e,
+                    };
+                    self.on_agreement_changed();
+                }
+            }
+        });
+
+        ui.add_space(5.0);
+        ui.label(
+            egui::RichText::new("To take dollars out of the channel, use Send.")
+                .size(12.0)
+                .color(Color32::GRAY),
+        );
+        if !can_change {
+            ui.label(egui::RichText::new(NO_LSP_OFFER_NOTE).size(12.0).color(Color32::GRAY));
+        }
+    }
+
+    fn show_send_screen(&mut self, ctx: &egui::Context) {
+        egui::CentralPanel::default().show(ctx, |ui| {
+            ui.vertical_centered(|ui| {
+                ui.add_space(20.0);
+                ui.heading(
+                    egui::RichText::new("Send")
+                        .size(24.0)
+                        .strong()
+                        .color(egui::Color32::WHITE),
+                );
+                ui.add_space(10.0);
+                ui.label("Paste a Lightning invoice, offer, LNURL or Lightning address:");
+                ui.add_space(8.0);
+
+                let response = ui.add(
+                    egui::TextEdit::multiline(&mut self.send_input)
+                        .desired_width(400.0)
+                        .desired_rows(3)
+                        .hint_text("lnbc..., lno..., lnurl... or name@domain"),
+                );
+                if response.changed() {
+                    self.send_request = None;
+                }
+
+                ui.add_space(8.0);
+                if ui.button("Check").clicked() {
+                    match parse_payment_request(&self.config.node.http_agent(), &self.send_input) {
+                        Ok(request) => {
+                            self.status_message.clear();
+                            self.send_request = Some(request);
+                        }
+                        Err(e) => self.status_message = e,
+                    }
+                }
+
+                let price = self.stable_channel.latest_price;
+                let mut amount_msat = None;
+                if let Some(request) = &self.send_request {
+                    ui.add_space(10.0);
+                    let description = request.description();
+                    if !description.is_empty() {
+                        ui.label(format!("Description: {}", description));
+                    }
+
+                    amount_msat = match request.amount_msat() {
+                        Some(msat) => Some(msat),
+                        None => {
+                            ui.label("Amount (USD):");
+                            ui.text_edit_singleline(&mut self.send_amount);
+                            self.send_amount
+                                .trim()
+                                .parse::<f64>()
+                                .ok()
+                                .filter(|usd| *usd > 0.0)
+                                .map(|usd| USD::from_f64(usd).to_msats(price))
+                        }
+                    };
+
+                    if let Some(msat) = amount_msat {
+                        let btc = Bitcoin::from_sats(msat / 1000);
+                        ui.label(
+                            egui::RichText::new(USD::from_bitcoin(btc, price).to_string())
+                                .size(28.0)
+                                .strong(),
+                        );
+                        ui.label(format!("{} at ${:.2}", btc, price));
+                    }
+                }
+
+                ui.add_space(10.0);
+                if ui
+                    .add_enabled(
+                        amount_msat.is_some() && self.lsp_offer.is_some(),
+                        egui::Button::new("Pay").min_size(egui::vec2(120.0, 36.0)).rounding(6.0),
+                    )
+                    .clicked()
+                {
+                    if let (Some(request), Some(msat)) = (&self.send_request, amount_msat) {
+                        let usd = USD::from_bitcoin(Bitcoin::from_sats(msat / 1000), price);
+                        match pay(&self.user, &self.config.node.http_agent(), request, msat) {
+                            Ok(payment_id) => {
+                                self.pending_stable_sends.push((payment_id.0, usd));
+                                self.status_message = format!("Sending {}...", usd);
+                                self.send_input.clear();
+                                self.send_amount.clear();
+                                self.send_request = None;
+                            }
+                            Err(e) => self.status_message = e,
+                        }
+                    }
+                }
+                if self.lsp_offer.is_none() {
+                    ui.label(egui::RichText::new(NO_LSP_OFFER_NOTE).size(12.0).color(Color32::GRAY));
+                }
+
+                ui.add_space(5.0);
+                if ui.button("Back").clicked() {
+                    self.state = AppState::MainScreen;
+                }
+
+                ui.add_space(10.0);
+                if !self.status_message.is_empty() {
+                    ui.label(self.status_message.clone());
+                }
+            });
+        });
+    }
+
+    fn show_receive_screen(&mut self, ctx: &egui::Context) {
+        egui::CentralPanel::default().show(ctx, |ui| {
+            ui.vertical_centered(|ui| {
+                ui.add_space(20.0);
+                ui.heading(
+                    egui::RichText::new("Receive")
+                        .size(24.0)
+                        .strong()
+                        .color(egui::Color32::WHITE),
+                );
+                ui.add_space(10.0);
+                ui.label("Amount (USD):");
+                ui.text_edit_singleline(&mut self.receive_amount);
+
+                let amount = self
+                    .receive_amount
+                    .trim()
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|usd| *usd > 0.0)
+                    .map(USD::from_f64);
+                if let Some(amount) = amount {
+                    let btc = Bitcoin::from_usd(amount, self.stable_channel.latest_price);
+                    ui.label(format!("{} at ${:.2}", btc, self.stable_channel.latest_price));
+                }
+
+                ui.add_space(8.0);
+                if ui
+                    .add_enabled(amount.is_some() && self.lsp_offer.is_some(), egui::Button::new("Create Invoice").rounding(6.0))
+                    .clicked()
+                {
+                    if let Some(amount) = amount {
+                        self.create_stable_invoice(amount);
+                    }
+                }
+                if self.lsp_offer.is_none() {
+                    ui.label(egui::RichText::new(NO_LSP_OFFER_NOTE).size(12.0).color(Color32::GRAY));
+                }
+
+                if !self.receive_qr.is_empty() {
+                    ui.add_space(10.0);
+                    self.receive_qr.show(ui, 256.0);
+                    ui.add(
+                        egui::TextEdit::multiline(&mut self.receive_invoice)
+                            .desired_width(400.0)
+                            .desired_rows(3),
+                    );
+                    if ui.button("Copy Invoice").clicked() {
+                        ctx.output_mut(|o| {
+                            o.copied_text = self.receive_invoice.clone();
+                        });
+                    }
+                    if ui.button("Save QR").clicked() {
+                        let data = self.receive_qr.data().to_string();
+                        self.save_qr(&data, "stable-channel-receive");
+                    }
+                }
+
+                ui.add_space(5.0);
+                if ui.button("Back").clicked() {
+                    self.state = AppState::MainScreen;
+                }
+
+                ui.add_space(10.0);
+                if !self.status_message.is_empty() {
+                    ui.label(self.status_message.clone());
+                }
+            });
+        });
+    }
+
+    fn show_backup_screen(&mut self, ctx: &egui::Context) {
+        egui::CentralPanel::default().show(ctx, |ui| {
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                ui.vertical_centered(|ui| {
+                    ui.add_space(20.0);
+                    ui.heading(
+                        egui::RichText::new("Back up your wallet")
+                            .size(24.0)
+                            .strong()
+                            .color(egui::Color32::WHITE),
+                    );
+                    ui.add_space(10.0);
+
+                    match self.mnemonic.clone() {
+                        Some(mnemonic) => {
+                            ui.label("Write these words down in order and keep them somewhere safe and offline.");
+                            ui.label("Anyone with them can take your funds.");
+                            ui.add_space(10.0);
+                            show_words(ui, &mnemonic);
+                            ui.add_space(20.0);
+                            ui.label("Now enter these words to confirm your backup:");
+                            self.backup_quiz.show(ui);
+                            ui.add_space(10.0);
+                            if ui.button("Confirm").clicked() {
+                                if self.backup_quiz.passed(&mnemonic) {
+                                    self.confirm_backup();
+                                } else {
+                                    self.status_message = "Those words don't match, check your backup".to_string();
+                                }
+                            }
+                        }
+                        None => {
+                            // Wallets created before recovery phrases only have LDK's raw seed.
+                            ui.label("This wallet was created without a recovery phrase.");
+                            ui.label("Copy this file somewhere safe and offline:");
+                            ui.add_space(5.0);
+                            ui.label(
+                                egui::RichText::new(seed_file_path(&self.config.node_dir()).display().to_string())
+                                    .monospace(),
+                            );
+                            ui.add_space(10.0);
+                            if ui.button("I've backed it up").clicked() {
+                                self.confirm_backup();
+                            }
+                        }
+                    }
+
+                    ui.add_space(10.0);
+                    if ui.button("Back").clicked() {
+                        self.status_message.clear();
+                        self.state = screen_for(self.lifecycle);
+                    }
+
+                    ui.add_space(10.0);
+                    if !self.status_message.is_empty() {
+                        ui.label(self.status_message.clone());
+                    }
+                });
+            });
+        });
+    }
+
+    /// Starts writing the encrypted channel backup to the chosen folder, if
+    /// any, on a background thread.
+    fn export_channel_backup(&mut self) {
+        if self.channel_backup_job.is_some() {
+            return;
+        }
+        self.channel_backup_due = false;
+        self.channel_backup_due_since = None;
+        let (Some(mnemonic), Some(dir)) = (&self.mnemonic, &self.channel_backup.dir) else {
+            return;
+        };
+        let key = backup_key(mnemonic);
+        let node_dir = self.config.node_dir();
+        let state_dir = self.config.stable_channel_dir();
+        let dest_dir = PathBuf::from(dir);
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let _ = sender.send(export_backup(&key, &node_dir, &state_dir, &dest_dir));
+        });
+        self.channel_backup_job = Some(receiver);
+    }
+
+    /// Exports once changes have settled, and picks up finished exports.
+    fn poll_channel_backup(&mut self) {
+        if let Some(job) = &self.channel_backup_job {
+            let result = match job.try_recv() {
+                Ok(result) => result,
+                Err(mpsc::TryRecvError::Empty) => return,
+                Err(mpsc::TryRecvError::Disconnected) => Err("Channel backup stopped unexpectedly".to_string()),
+            };
+            self.channel_backup_job = None;
+            match result {
+                Ok(path) => {
+                    println!("Channel backup written to {}", path.display());
+                    self.channel_backup.last_export = Some(unix_now());
+                    if let Err(e) = save_channel_backup_settings(&self.config.stable_channel_dir(), &self.channel_backup) {
+                        eprintln!("Failed to save channel backup settings: {}", e);
+                    }
+                }
+                Err(e) => {
+                    eprintln!("{}", e);
+                    self.status_message = e;
+                }
+            }
+        }
+
+        if self.channel_backup_due {
+            let since = *self.channel_backup_due_since.get_or_insert_with(Instant::now);
+            if since.elapsed() >= CHANNEL_BACKUP_DELAY {
+                self.export_channel_backup();
+            }
+        }
+    }
+
+    fn show_channel_backup(&mut self, ui: &mut egui::Ui) {
+        if self.mnemonic.is_none() {
+            ui.label("Channel backups need a wallet with a recovery phrase.");
+            return;
+        }
+        ui.label("An encrypted copy of your channel state, updated whenever it changes.");
+        ui.label("Keep it somewhere that survives this device, like a synced folder.");
+        ui.add_space(5.0);
+        match &self.channel_backup.dir {
+            Some(dir) => ui.label(format!("Folder: {}", dir)),
+            None => ui.label("No folder chosen"),
+        };
+        if let Some(last_export) = self.channel_backup.last_export {
+            let ago = unix_now().saturating_sub(last_export).max(0) as u64;
+            ui.label(format!("Last backup: {} ago", format_duration(ago)));
+        }
+        ui.add_space(5.0);
+        ui.horizontal(|ui| {
+            if ui.button("Choose Folder").clicked() {
+                if let Some(dir) = rfd::FileDialog::new().pick_folder() {
+                    self.channel_backup.dir = Some(dir.display().to_string());
+                    self.channel_backup_due = true;
+                }
+            }
+            if ui
+                .add_enabled(self.channel_backup.dir.is_some(), egui::Button::new("Back Up Now"))
+                .clicked()
+            {
+                self.export_channel_backup();
+            }
+        });
+    }
+
+    fn show_encryption(&mut self, ui: &mut egui::Ui) {
+        if data_key().is_some() {
+            ui.label("Your wallet and app data are encrypted with your passphrase.");
+            return;
+        }
+        ui.label("Set a passphrase to encrypt your wallet seed and app data on this device.");
+        ui.label(
+            egui::RichText::new(
+                "You'll need it every time the app starts. If you forget it, restore from your recovery phrase.",
+            )
+            .size(12.0)
+            .color(Color32::GRAY),
+        );
+        ui.add_space(5.0);
+        ui.label("Passphrase:");
+        ui.add(egui::TextEdit::singleline(&mut self.new_passphrase).password(true));
+        ui.label("Confirm passphrase:");
+        ui.add(egui::TextEdit::singleline(&mut self.confirm_passphrase).password(true));
+        ui.add_space(5.0);
+        if ui.button("Encrypt").clicked() {
+            self.enable_encryption();
+        }
+    }
+
+    fn enable_encryption(&mut self) {
+        if self.new_passphrase.len() < 8 {
+            self.status_message = "Use a passphrase of at least 8 characters".to_string();
+            return;
+        }
+        if self.new_passphrase != self.confirm_passphrase {
+            self.status_message = "Passphrases don't match".to_string();
+            return;
+        }
+
+        let node_dir = self.config.node_dir();
+        // The salt is written before anything is encrypted with the key, or
+        // an interrupted run could leave files nobody can decrypt.
+        let saved = EncryptionParams::create(&self.new_passphrase)
+            .and_then(|(params, key)| params.save(&node_dir).map(|_| key));
+
+        self.new_passphrase.clear();
+        self.confirm_passphrase.clear();
+        self.status_message = match saved {
+            Ok(key) => {
+                set_data_key(key);
+                match finish_encryption(&self.config) {
+                    Ok(()) => "Wallet encrypted".to_string(),
+                    Err(e) => {
+                        clear_data_key();
+                        format!(
+                            "{}. Your passphrase is set; encryption will finish the next time you unlock.",
+                            e
+                        )
+                    }
+                }
+            }
+            Err(e) => e,
+        };
+    }
+
+    fn confirm_backup(&mut self) {
+        self.backup_confirmed = true;
+        if let Err(e) = save_backup_confirmed(&self.config.stable_channel_dir(), true) {
+            eprintln!("Failed to save backup status: {}", e);
+        }
+        self.status_message.clear();
+        self.state = screen_for(self.lifecycle);
+    }
+
+    /// Expandable table of what each price feed returned on the last fetch.
+    fn show_price_sources(&self, ui: &mut egui::Ui) {
+        let quotes = &self.stable_channel.prices;
+        let answered = quotes.iter().filter(|q| q.price.is_some()).count();
+        egui::CollapsingHeader::new(format!("Price sources ({}/{})", answered, quotes.len()))
+            .id_salt("price_sources")
+            .show(ui, |ui| {
+                if quotes.is_empty() {
+                    ui.label("No prices fetched yet.");
+                    return;
+                }
+                egui::Grid::new("price_sources_grid")
+                    .striped(true)
+                    .spacing([16.0, 4.0])
+                    .show(ui, |ui| {
+                        ui.strong("Source");
+                        ui.strong("Price");
+                        ui.strong("Latency");
+                        ui.strong("Status");
+                        ui.end_row();
+
+                        for quote in quotes {
+                            ui.label(&quote.name);
+                            match quote.price {
+                                Some(price) => ui.label(format!("${:.2}", price)),
+                                None => ui.label("-"),
+                            };
+                            if quote.live {
+                                ui.label("live");
+                            } else {
+                                ui.label(format!("{} ms", quote.latency_ms));
+                            }
+                            if let Some(error) = &quote.error {
+                                ui.label(egui::RichText::new("Failed").color(Color32::LIGHT_RED))
+                                    .on_hover_text(error);
+                            } else if quote.excluded {
+                                ui.label(egui::RichText::new("Excluded").color(Color32::from_rgb(255, 200, 0)))
+                                    .on_hover_text("Too far from the other sources, left out of the median");
+                            } else if quote.stale {
+                                ui.label(egui::RichText::new("Cached").color(Color32::GRAY))
+                                    .on_hover_text("From the previous fetch, the source can't be asked again yet");
+                            } else if let Some(attestation) = &quote.attestation {
+                                ui.label(egui::RichText::new("Signed").color(Color32::GREEN))
+                                    .on_hover_text(format!("Signed by oracle {}", attestation.oracle));
+                            } else {
+                                ui.label(egui::RichText::new("Used").color(Color32::GREEN));
+                            }
+                            ui.end_row();
+                        }
+                    });
+                if let Some(updated) = self.stable_channel.prices_updated {
+                    let ago = unix_now().saturating_sub(updated).max(0) as u64;
+                    ui.label(
+                        egui::RichText::new(format!("Fetched {} ago", format_duration(ago)))
+                            .size(12.0)
+                            .color(Color32::GRAY),
+                    );
+                }
+            });
+    }
+
+    fn show_chart_screen(&mut self, ctx: &egui::Context) {
+        let now = unix_now();
+        let points = self.price_history.window(self.chart_window_secs, now);
+        // Hours relative to now, so the axis reads "hours ago".
+        let x = |timestamp: i64| (timestamp - now) as f64 / 3600.0;
+        let series = |value: &dyn Fn(&PricePoint) -> Option<f64>| -> Vec<[f64; 2]> {
+            points
+                .iter()
+                .filter_map(|point| value(point).map(|y| [x(point.timestamp), y]))
+                .collect()
+        };
+
+        egui::CentralPanel::default().show(ctx, |ui| {
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                ui.vertical_centered(|ui| {
+                    ui.add_space(20.0);
+                    ui.heading(
+                        egui::RichText::new("Price History")
+                            .size(24.0)
+                            .strong()
+                            .color(egui::Color32::WHITE),
+                    );
+                    ui.add_space(10.0);
+
+                    ui.horizontal(|ui| {
+                        ui.add_space((ui.available_width() - 320.0).max(0.0) / 2.0);
+                        for (label, secs) in CHART_WINDOWS {
+                            ui.selectable_value(&mut self.chart_window_secs, secs, label);
+                        }
+                        ui.checkbox(&mut self.chart_show_feeds, "Feed quotes");
+                    });
+                    ui.add_space(10.0);
+
+                    if points.len() < 2 {
+                        ui.label("Not enough history yet. A point is recorded every couple of minutes.");
+                    }
+                });
+
+                ui.label(egui::RichText::new("BTC/USD").strong());
+                Plot::new("price_plot")
+                    .height(220.0)
+                    .legend(Legend::default())
+                    .x_axis_label("hours ago")
+                    .allow_scroll(false)
+                    .show(ui, |plot_ui| {
+                        plot_ui.line(Line::new(series(&|p| Some(p.median_price))).name("Median").width(2.0));
+                        if self.chart_show_feeds {
+                            for name in PriceHistory::feed_names(points) {
+                                let quotes = series(&|p| p.quotes.iter().find(|(n, _)| *n == name).map(|(_, q)| *q));
+                                plot_ui.line(Line::new(quotes).name(&name));
+                            }
+                        }
+                    });
+
+                ui.add_space(10.0);
+                ui.label(egui::RichText::new("Your balance in USD").strong());
+                Plot::new("usd_plot")
+                    .height(160.0)
+                    .x_axis_label("hours ago")
+                    .include_y(0.0)
+                    .allow_scroll(false)
+                    .show(ui, |plot_ui| {
+                        plot_ui.line(Line::new(series(&|p| Some(p.receiver_usd))).name("USD value"));
+                    });
+
+                ui.add_space(10.0);
+                ui.label(egui::RichText::new("Your balance in sats").strong());
+                Plot::new("btc_plot")
+                    .height(160.0)
+                    .x_axis_label("hours ago")
+                    .include_y(0.0)
+                    .allow_scroll(false)
+                    .show(ui, |plot_ui| {
+                        plot_ui.line(Line::new(series(&|p| Some(p.receiver_sats as f64))).name("Sats"));
+                    });
+
+                ui.add_space(20.0);
+                ui.vertical_centered(|ui| {
+                    if ui.button("Back").clicked() {
+                        self.state = screen_for(self.lifecycle);
+                    }
+                });
+                ui.add_space(20.0);
+            });
+        });
+    }
+
+    fn open_onchain_screen(&mut self) {
+        self.status_message.clear();
+        self.last_onchain_refresh = None;
+        self.state = AppState::OnchainScreen;
+    }
+
+    fn save_onchain_log(&self) {
+        if let Err(e) = save_onchain_log(&self.config.stable_channel_dir(), &self.onchain_log) {
+            eprintln!("Failed to save on-chain log: {}", e);
+        }
+    }
+
+    fn refresh_onchain_history(&mut self) {
+        let esplora = Esplora::new(self.config.node.http_agent(), &self.config.node.chain_source_url);
+        self.onchain_fee_rate = esplora.fee_rate(6).ok();
+        match fetch_history(&esplora, &self.onchain_log) {
+            Ok(history) => {
+                self.onchain_history = history;
+                self.onchain_error = None;
+            }
+            Err(e) => self.onchain_error = Some(format!("Chain source unavailable: {}", e)),
+        }
+        self.last_onchain_refresh = Some(Instant::now());
+    }
+
+    fn new_onchain_address(&mut self) {
+        match self.user.onchain_payment().new_address() {
+            Ok(address) => {
+                self.onchain_address = address.to_string();
+                self.onchain_qr.set(&format!("bitcoin:{}", self.onchain_address));
+                self.onchain_log.record_address(&self.onchain_address);
+                self.save_onchain_log();
+            }
+            Err(e) => self.status_message = format!("Failed to get address: {}", e),
+        }
+    }
+
+    fn send_onchain(&mut self) {
+        let address = match validate_withdrawal_address(&self.onchain_send_address, self.config.node.network()) {
+            Ok(address) => address,
+            Err(e) => {
+                self.status_message = e;
+                return;
+            }
+        };
+
+        let spendable = self.user.list_balances().spendable_onchain_balance_sats;
+        let result = if self.onchain_send_all {
+            self.user
+                .onchain_payment()
+                .send_all_to_address(&address)
+                .map(|txid| (txid, None))
+        } else {
+            let amount_sats = match self.onchain_send_amount.trim().parse::<u64>() {
+                Ok(sats) if sats > 0 && sats <= spendable => sats,
+                Ok(_) => {
+                    self.status_message = format!("Amount must be between 1 and {} sats", spendable);
+                    return;
+                }
+                Err(_) => {
+                    self.status_message = "Invalid amount".to_string();
+                    return;
+                }
+            };
+            self.user
+                .onchain_payment()
+                .send_to_address(&address, amount_sats)
+                .map(|txid| (txid, Some(amount_sats)))
+        };
+
+        match result {
+            Ok((txid, amount_sats)) => {
+                self.onchain_log.record_send(&txid.to_string(), &address.to_string(), amount_sats);
+                self.save_onchain_log();
+                self.onchain_send_address.clear();
+                self.onchain_send_amount.clear();
+                self.onchain_send_all = false;
+                self.last_onchain_refresh = None;
+                self.status_message = format!("Sent in {}", txid);
+            }
+            Err(e) => self.status_message = format!("Failed to send: {}", e),
+        }
+    }
+
+    fn show_onchain_screen(&mut self, ctx: &egui::Context) {
+        let refresh_due = self
+            .last_onchain_refresh
+            .map_or(true, |last| last.elapsed() >= Duration::from_secs(60));
+        if refresh_due {
+            self.refresh_onchain_history();
+        }
+        ctx.request_repaint_after(Duration::from_secs(60));
+
+        let balances = self.user.list_balances();
+        let price = self.stable_channel.latest_price;
+        let tip_height = self.user.status().current_best_block.height;
+
+        egui::CentralPanel::default().show(ctx, |ui| {
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                ui.vertical_centered(|ui| {
+                    ui.add_space(20.0);
+                    ui.heading(
+                        egui::RichText::new("On-chain Wallet")
+                            .size(24.0)
+                            .strong()
+                            .color(egui::Color32::WHITE),
+                    );
+                    ui.add_space(10.0);
+
+                    let spendable = Bitcoin::from_sats(balances.spendable_onchain_balance_sats);
+                    ui.group(|ui| {
+                        ui.add_space(10.0);
+                        ui.add(egui::Label::new(egui::RichText::new(spendable.to_string()).size(24.0).strong()));
+                        ui.label(format!("Value: {}", USD::from_bitcoin(spendable, price)));
+                        let unconfirmed = balances
+                            .total_onchain_balance_sats
+                            .saturating_sub(balances.spendable_onchain_balance_sats);
+                        if unconfirmed > 0 {
+                            ui.label(
+                                egui::RichText::new(format!("{} not yet spendable", Bitcoin::from_sats(unconfirmed)))
+                                    .size(12.0)
+                                    .color(Color32::GRAY),
+                            );
+                        }
+                        ui.add_space(10.0);
+                    });
+                    ui.add_space(20.0);
+
+                    ui.collapsing("Receive", |ui| {
+                        if ui.button("New Address").clicked() {
+                            self.new_onchain_address();
+                        }
+                        if !self.onchain_qr.is_empty() {
+                            ui.add_space(10.0);
+                            self.onchain_qr.show(ui, 200.0);
+                            ui.label(&self.onchain_address);
+                            if ui.button("Copy Address").clicked() {
+                                ctx.output_mut(|o| {
+                                    o.copied_text = self.onchain_address.clone();
+                                });
+                            }
+                        }
+                    });
+
+                    ui.collapsing("Send", |ui| {
+                        ui.label("Address:");
+                        ui.text_edit_singleline(&mut self.onchain_send_address);
+                        ui.checkbox(&mut self.onchain_send_all, "Send everything");
+                        if !self.onchain_send_all {
+                            ui.label("Amount (sats):");
+                            ui.text_edit_singleline(&mut self.onchain_send_amount);
+                        }
+                        // ldk-node 0.4 picks the fee rate itself, so we can only show it.
+                        if let Some(rate) = self.onchain_fee_rate {
+                            ui.label(
+                                egui::RichText::new(format!("Network fee: about {:.1} sat/vB, set by the wallet", rate))
+                                    .size(12.0)
+                                    .color(Color32::GRAY),
+                            );
+                        }
+                        ui.add_space(5.0);
+                        if ui.button("Send").clicked() {
+                            self.send_onchain();
+                        }
+                    });
+
+                    ui.add_space(20.0);
+                    ui.label(egui::RichText::new("Deposits and withdrawals").strong());
+                    ui.label(
+                        egui::RichText::new(
+                            "Payments to addresses from this app and sends made from it. Channel opens, \
+                             closes and change outputs aren't listed, but are in the balance.",
+                        )
+                        .size(12.0)
+                        .color(Color32::GRAY),
+                    );
+                    if self.onchain_history.is_empty() {
+                        ui.label("No deposits or withdrawals yet");
+                    }
+                    Grid::new("onchain_history").striped(true).show(ui, |ui| {
+                        for tx in &self.onchain_history {
+                            let amount = match tx.amount_sats {
+                                Some(sats) => Bitcoin::from_sats(sats).to_string(),
+                                None => "All funds".to_string(),
+                            };
+                            ui.label(if tx.incoming { "Received" } else { "Sent" });
+                            ui.label(amount);
+                            ui.label(match tx.block_height {
+                                Some(height) => format!("{} conf", tip_height.saturating_sub(height) + 1),
+                                None => "Unconfirmed".to_string(),
+                            });
+                            ui.label(egui::RichText::new(&tx.txid[..16]).monospace())
+                                .on_hover_text(format!("{}\n{}", tx.txid, tx.address));
+                            ui.end_row();
+                        }
+                    });
+                    if let Some(error) = &self.onchain_error {
+                        ui.label(egui::RichText::new(error).color(Color32::LIGHT_RED));
+                    }
+
+                    ui.add_space(20.0);
+                    if ui.button("Back").clicked() {
+                        self.status_message.clear();
+                        self.state = screen_for(self.lifecycle);
+                    }
+
+                    ui.add_space(10.0);
+                    if !self.status_message.is_empty() {
+                        ui.label(self.status_message.clone());
+                    }
+                });
+            });
+        });
+    }
+
+    fn save_qr(&mut self, data: &str, name: &str) {
+        let dir = dirs::download_dir()
+            .or_else(dirs::home_dir)
+            .unwrap_or_default();
+        let path = dir.join(format!("{}.png", name));
+        self.status_message = match qr::save_png(data, &path) {
+            Ok(()) => format!("Saved QR code to {}", path.display()),
+            Err(e) => e,
+        };
+    }
+
+    /// Called whenever `expected_usd` or `native_btc` change.
+    fn on_agreement_changed(&mut self) {
+        if self.lsp_offer.is_some() {
+            self.stable_channel.agreement_pending = true;
+            self.send_agreement();
+        } else {
+            eprintln!("No LSP offer configured, the LSP isn't told of the new stable balance");
+        }
+        self.save_stable_channel();
+        self.channel_backup_due = true;
+    }
+
+    /// Sends the agreement unless an update is already on its way, in which
+    /// case it is sent again once that one arrives.
+    fn send_agreement(&mut self) {
+        if self.agreement_update.is_some() {
+            return;
+        }
+        let Some(offer) = &self.lsp_offer else {
+            return;
+        };
+        match send_agreement(&self.user, &mut self.stable_channel, offer) {
+            Ok(update) => self.agreement_update = Some(update),
+            Err(e) => eprintln!("{}", e),
+        }
+        self.save_stable_channel();
+    }
+
+    fn on_agreement_sent(&mut self) {
+        let Some(update) = self.agreement_update.take() else {
+            return;
+        };
+        if update.agreement == StableAgreement::new(&self.stable_channel) {
+            println!("Counterparty has the agreement for {}", update.agreement.expected_usd);
+            self.stable_channel.agreement_pending = false;
+            self.save_stable_channel();
+        } else {
+            // Changed while the update was on its way.
+            self.send_agreement();
+        }
+    }
+
+    pub fn connect_to_lsp_and_entry_node(&mut self) {
+        let _connected_to_lsp = self.user.connect(
+            PublicKey::from_str("0367631f3a8ca46bccf6d8eae8b728963337f8a6825199386c9a48987ea82b54cd")
+                .unwrap(),
+            SocketAddress::from_str("127.0.0.1:9737").unwrap(),
+            true,
+        );
+    
+        println!("Connection result: {:?}", _connected_to_lsp.unwrap());
+
+        let _connected_to_exchange = self.user.connect(
+            PublicKey::from_str("03e9d73c317a6113a30e85d7dafcebaa509c1744e0528d392ae975d2e4177d11dc")
+                .unwrap(),
+            SocketAddress::from_str("127.0.0.1:9735").unwrap(),
+            true,
+        );
+    
+        println!("Connection result: {:?}", _connected_to_exchange.unwrap());
+    
+
+        let node_info = self.user
+            .network_graph()
+            .node(&NodeId::from_pubkey(
+                &PublicKey::from_str("03ebdb4d14e3101c1d63e3d5555db2d15bc50d32bc30919b7dfd3d35609b978ff4")
+                    .unwrap(),
+            ));
+    
+        println!("Node information: {:?}", node_info);
     
         let node_info = self.user
             .network_graph()
@@ -601,25 +2841,432 @@ impl MyApp {
     }
 }
 
+/// Steps of the first launch, before any wallet exists.
+enum SetupStep {
+    Welcome,
+    ShowWords,
+    Quiz,
+    Restore,
+}
+
+/// Creates or restores the wallet's recovery phrase on first launch. The
+/// node can only be built once we know its seed.
+struct SetupFlow {
+    config: Option<Config>,
+    step: SetupStep,
+    mnemonic: Option<Mnemonic>,
+    quiz: BackupQuiz,
+    restore_input: String,
+    restore_backup: Option<PathBuf>,
+    /// The user confirmed the chosen backup is their latest.
+    restore_backup_latest: bool,
+    error: Option<String>,
+}
+
+impl SetupFlow {
+    fn new(config: Config) -> Self {
+        Self {
+            config: Some(config),
+            step: SetupStep::Welcome,
+            mnemonic: None,
+            quiz: BackupQuiz::new(12),
+            restore_input: String::new(),
+            restore_backup: None,
+            restore_backup_latest: false,
+            error: None,
+        }
+    }
+
+    /// Draws the current step. Hands the config back once the recovery
+    /// phrase has been saved.
+    fn show(&mut self, ctx: &egui::Context) -> Option<Config> {
+        let mut finished = None;
+
+        egui::CentralPanel::default().show(ctx, |ui| {
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                ui.vertical_centered(|ui| {
+                    ui.add_space(40.0);
+                    ui.heading(egui::RichText::new("Stable Channels").size(28.0).strong());
+                    ui.add_space(20.0);
+
+                    match self.step {
+                        SetupStep::Welcome => {
+                            if ui.add(egui::Button::new("Create a new wallet").min_size(egui::vec2(220.0, 36.0))).clicked() {
+                                let mnemonic = generate_mnemonic();
+                                self.quiz = BackupQuiz::new(mnemonic.word_count());
+                                self.mnemonic = Some(mnemonic);
+                                self.step = SetupStep::ShowWords;
+                            }
+                            ui.add_space(10.0);
+                            if ui.add(egui::Button::new("Restore from recovery phrase").min_size(egui::vec2(220.0, 36.0))).clicked() {
+                                self.step = SetupStep::Restore;
+                            }
+                        }
+                        SetupStep::ShowWords => {
+                            ui.label("This is your recovery phrase. Write the words down in order and keep them offline.");
+                            ui.label("They are the only way to get your funds back if you lose this device.");
+                            ui.add_space(10.0);
+                            if let Some(mnemonic) = &self.mnemonic {
+                                show_words(ui, mnemonic);
+                            }
+                            ui.add_space(20.0);
+                            if ui.button("I've written them down").clicked() {
+                                self.step = SetupStep::Quiz;
+                            }
+                        }
+                        SetupStep::Quiz => {
+                            ui.label("Enter these words to confirm your backup:");
+                            ui.add_space(10.0);
+                            self.quiz.show(ui);
+                            ui.add_space(10.0);
+                            ui.horizontal(|ui| {
+                                if ui.button("Confirm").clicked() {
+                                    match &self.mnemonic {
+                                        Some(mnemonic) if self.quiz.passed(mnemonic) => {
+                                            finished = self.finish(true);
+                                        }
+                                        _ => self.error = Some("Those words don't match, check your backup".to_string()),
+                                    }
+                                }
+                                if ui.button("Show words again").clicked() {
+                                    self.error = None;
+                                    self.step = SetupStep::ShowWords;
+                                }
+                                if ui.button("Skip for now").clicked() {
+                                    finished = self.finish(false);
+                                }
+                            });
+                        }
+                        SetupStep::Restore => {
+                            ui.label("Enter your 12 or 24 word recovery phrase:");
+                            ui.add_space(10.0);
+                            ui.add(
+                                egui::TextEdit::multiline(&mut self.restore_input)
+                                    .desired_width(400.0)
+                                    .desired_rows(3),
+                            );
+                            ui.label(
+                                egui::RichText::new(
+                                    "Your on-chain funds are recovered from the phrase. Open channels need a channel backup.",
+                                )
+                                .size(12.0)
+                                .color(Color32::GRAY),
+                            );
+                            ui.add_space(10.0);
+                            ui.horizontal(|ui| {
+                                if ui.button("Choose Channel Backup").clicked() {
+                                    self.restore_backup = rfd::FileDialog::new().pick_file();
+                                    self.restore_backup_latest = false;
+                                }
+                                if let Some(path) = &self.restore_backup {
+                                    ui.label(path.display().to_string());
+                                }
+                            });
+                            if self.restore_backup.is_some() {
+                                ui.label(
+                                    egui::RichText::new(
+                                        "Only restore your latest backup. Running from an older channel state can lose you the channel's funds.",
+                                    )
+                                    .size(12.0)
+                                    .color(Color32::from_rgb(255, 200, 0)),
+                                );
+                                ui.checkbox(
+                                    &mut self.restore_backup_latest,
+                                    "This is my latest backup and the app isn't running anywhere else",
+                                );
+                            }
+                            ui.add_space(10.0);
+                            let can_restore = self.restore_backup.is_none() || self.restore_backup_latest;
+                            ui.horizontal(|ui| {
+                                if ui.add_enabled(can_restore, egui::Button::new("Restore")).clicked() {
+                                    match self.restore() {
+                                        Ok(()) => {
+                                            // They just typed it in, so it's backed up.
+                                            finished = self.finish(true);
+                                        }
+                                        Err(e) => self.error = Some(e),
+                                    }
+                                }
+                                if ui.button("Back").clicked() {
+                                    self.error = None;
+                                    self.step = SetupStep::Welcome;
+                                }
+                            });
+                        }
+                    }
+
+                    if let Some(error) = &self.error {
+                        ui.add_space(10.0);
+                        ui.label(egui::RichText::new(error).color(Color32::LIGHT_RED));
+                    }
+                });
+            });
+        });
+
+        finished
+    }
+
+    /// Checks the recovery phrase and puts the channel backup, if any, in
+    /// place for the node to start from.
+    fn restore(&mut self) -> Result<(), String> {
+        let mnemonic = parse_mnemonic(&self.restore_input)?;
+        if let (Some(path), Some(config)) = (&self.restore_backup, &self.config) {
+            let created_at = import_backup(
+                &backup_key(&mnemonic),
+                path,
+                &config.node_dir(),
+                &config.stable_channel_dir(),
+            )?;
+            println!("Restored channel backup from {}", created_at);
+        }
+        self.mnemonic = Some(mnemonic);
+        Ok(())
+    }
+
+    fn finish(&mut self, backup_confirmed: bool) -> Option<Config> {
+        let config = self.config.take()?;
+        let mnemonic = self.mnemonic.as_ref()?;
+        if let Err(e) = save_mnemonic(&config.node_dir(), mnemonic) {
+            self.error = Some(format!("Failed to save recovery phrase: {}", e));
+            self.config = Some(config);
+            return None;
+        }
+        if let Err(e) = save_backup_confirmed(&config.stable_channel_dir(), backup_confirmed) {
+            eprintln!("Failed to save backup status: {}", e);
+        }
+        Some(config)
+    }
+}
+
+/// Moves the seed and state files under the data key. The plaintext seed
+/// files are deleted only once everything else is encrypted, and the salt is
+/// already saved, so an interrupted run is simply finished at the next unlock.
+fn finish_encryption(config: &Config) -> Result<(), String> {
+    let node_dir = config.node_dir();
+    encrypt_seed_files(&node_dir).map_err(|e| format!("Failed to encrypt seed: {}", e))?;
+    reseal_state_files(&config.stable_channel_dir()).map_err(|e| format!("Failed to encrypt app data: {}", e))?;
+    remove_plaintext_seed_files(&node_dir).map_err(|e| format!("Failed to remove unencrypted seed: {}", e))
+}
+
+/// Asks for the passphrase before anything on disk is read.
+struct UnlockFlow {
+    config: Option<Config>,
+    params: EncryptionParams,
+    passphrase: String,
+    error: Option<String>,
+}
+
+impl UnlockFlow {
+    /// Hands the config back once the data key is set.
+    fn show(&mut self, ctx: &egui::Context) -> Option<Config> {
+        let mut submitted = false;
+
+        egui::CentralPanel::default().show(ctx, |ui| {
+            ui.vertical_centered(|ui| {
+                ui.add_space(80.0);
+                ui.heading(egui::RichText::new("Stable Channels").size(28.0).strong());
+                ui.add_space(20.0);
+                ui.label("Enter your passphrase to unlock your wallet:");
+                ui.add_space(10.0);
+                let response = ui.add(
+                    egui::TextEdit::singleline(&mut self.passphrase)
+                        .password(true)
+                        .desired_width(240.0),
+                );
+                submitted = response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+                ui.add_space(10.0);
+                submitted |= ui.button("Unlock").clicked();
+
+                if let Some(error) = &self.error {
+                    ui.add_space(10.0);
+                    ui.label(egui::RichText::new(error).color(Color32::LIGHT_RED));
+                }
+            });
+        });
+
+        if !submitted {
+            return None;
+        }
+        match self.params.unlock(&self.passphrase) {
+            Ok(key) => {
+                set_data_key(key);
+                self.passphrase.clear();
+                self.config.take()
+            }
+            Err(e) => {
+                self.passphrase.clear();
+                self.error = Some(e);
+                None
+            }
+        }
+    }
+}
+
+/// Unlocks the data directory if it is encrypted, runs the first-launch
+/// setup if there is no wallet yet, then the app.
+enum Launcher {
+    Unlock(UnlockFlow),
+    Setup(SetupFlow),
+    Running(Box<MyApp>),
+    /// The wallet files exist but couldn't be read.
+    Failed(String),
+}
+
+impl Launcher {
+    fn new(config: Config) -> Self {
+        if let Some(params) = EncryptionParams::load(&config.node_dir()) {
+            return Launcher::Unlock(UnlockFlow {
+                config: Some(config),
+                params,
+                passphrase: String::new(),
+                error: None,
+            });
+        }
+        Self::unlocked(config)
+    }
+
+    fn unlocked(config: Config) -> Self {
+        match find_seed(&config.node_dir()) {
+            Ok(WalletSeed::None) => Launcher::Setup(SetupFlow::new(config)),
+            Ok(seed) => Launcher::Running(Box::new(MyApp::new(config, seed))),
+            Err(e) => Launcher::Failed(e),
+        }
+    }
+}
+
+impl App for Launcher {
+    fn update(&mut self, ctx: &egui::Context, frame: &mut Frame) {
+        match self {
+            Launcher::Unlock(unlock) => {
+                if let Some(config) = unlock.show(ctx) {
+                    if plaintext_seed_files_exist(&config.node_dir()) {
+                        if let Err(e) = finish_encryption(&config) {
+                            eprintln!("{}", e);
+                        }
+                    }
+                    *self = Launcher::unlocked(config);
+                }
+            }
+            Launcher::Setup(setup) => {
+                if let Some(config) = setup.show(ctx) {
+                    *self = Launcher::unlocked(config);
+                }
+            }
+            Launcher::Running(app) => app.update(ctx, frame),
+            Launcher::Failed(error) => {
+                egui::CentralPanel::default().show(ctx, |ui| {
+                    ui.vertical_centered(|ui| {
+                        ui.add_space(80.0);
+                        ui.heading("Can't open your wallet");
+                        ui.add_space(10.0);
+                        ui.label(egui::RichText::new(error.as_str()).color(Color32::LIGHT_RED));
+                        ui.add_space(10.0);
+                        ui.label("Nothing was changed. Check the wallet files in your data directory, or restore from your recovery phrase in an empty one.");
+                    });
+                });
+            }
+        }
+    }
+}
+
+/// Must be called once the event loop is running.
+fn make_tray() -> Option<Tray> {
+    match Tray::new() {
+        Ok(tray) => Some(tray),
+        Err(e) => {
+            eprintln!("{}", e);
+            None
+        }
+    }
+}
+
+impl MyApp {
+    fn stable_usd(&self) -> USD {
+        let balances = self.user.list_balances();
+        let lightning_btc = Bitcoin::from_sats(balances.total_lightning_balance_sats);
+        USD::from_bitcoin(lightning_btc - self.stable_channel.native_btc, self.stable_channel.latest_price)
+    }
+
+    fn update_tray(&self) {
+        if let Some(tray) = &self.tray {
+            tray.update(self.stable_usd(), self.stable_channel.latest_price, self.stabilization_paused);
+        }
+    }
+
+    fn handle_tray(&mut self, ctx: &egui::Context) {
+        let Some(action) = self.tray.as_ref().and_then(|tray| tray.poll()) else {
+            return;
+        };
+        match action {
+            TrayAction::Open => {
+                ctx.send_viewport_cmd(egui::ViewportCommand::Minimized(false));
+                ctx.send_viewport_cmd(egui::ViewportCommand::Focus);
+            }
+            TrayAction::TogglePause => {
+                self.stabilization_paused = !self.stabilization_paused;
+                println!("Stabilization paused: {}", self.stabilization_paused);
+                self.update_tray();
+            }
+            TrayAction::Quit => {
+                self.quitting = true;
+                ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+            }
+        }
+    }
+}
+
 impl App for MyApp {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut Frame) {
         let now = Instant::now();
         
-        if now.duration_since(self.last_stability_check) >= Duration::from_secs(30) {
+        if now.duration_since(self.last_stability_check) >= Duration::from_secs(self.config.prices.check_interval_secs) {
             // self.connect_to_lsp_and_entry_node();
-            check_stability(&self.user, &mut self.stable_channel);
+            self.refresh_lifecycle();
+            self.track_counterparty();
+            self.try_deferred_sweep();
+            if self.lifecycle == ChannelLifecycle::Ready && !self.stabilization_paused {
+                self.run_stability_check();
+            }
+            self.update_tray();
             self.last_stability_check = now;
         }
 
+        self.handle_tray(ctx);
+        if self.tray.is_some() {
+            // Closing goes to the tray. The window is minimized rather than
+            // hidden, since hidden windows get no frames on some platforms and
+            // update() is what keeps the channel stable. The OS may still
+            // throttle a minimized window's frames (App Nap on macOS), so
+            // checks can run later than check_interval_secs.
+            if ctx.input(|i| i.viewport().close_requested()) && !self.quitting {
+                ctx.send_viewport_cmd(egui::ViewportCommand::CancelClose);
+                ctx.send_viewport_cmd(egui::ViewportCommand::Minimized(true));
+            }
+            ctx.request_repaint_after(Duration::from_secs(5));
+        }
+
+        if matches!(
+            self.jit_status,
+            Some(JitStatus::AwaitingPayment) | Some(JitStatus::DepositDetected)
+        ) {
+            self.check_onchain_deposit();
+        }
+
         match self.state {
             AppState::OnboardingScreen => self.show_onboarding_screen(ctx),
             AppState::WaitingForPayment => self.show_waiting_for_payment_screen(ctx),
             AppState::MainScreen => self.show_main_screen(ctx),
+            AppState::SendScreen => self.show_send_screen(ctx),
+            AppState::ReceiveScreen => self.show_receive_screen(ctx),
             AppState::ClosingScreen => self.show_closing_screen(ctx),
+            AppState::OnchainScreen => self.show_onchain_screen(ctx),
+            AppState::BackupScreen => self.show_backup_screen(ctx),
+            AppState::ChartScreen => self.show_chart_screen(ctx),
         }
+        self.notifier.show(ctx);
 
         self.poll_for_events();
-        
+        self.poll_channel_backup();
     }
 }
 fn main() {
@@ -644,7 +3291,9 @@ fn main() {
     let _ = eframe::run_native(
         "Stable Channels",
         native_options,
-        Box::new(|cc| Ok(Box::new(MyApp::new(cc, config)))),
+        // Built once the event loop runs: macOS only allows creating the tray
+        // icon from there.
+        Box::new(move |_cc| Ok(Box::new(Launcher::new(config)))),
     );
     println!("App has exited.");
 }
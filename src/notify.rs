@@ -0,0 +1,119 @@
+use std::time::{Duration, Instant};
+
+use eframe::egui;
+use egui::Color32;
+use serde::Deserialize;
+
+/// How long a toast stays on screen.
+const TOAST_DURATION: Duration = Duration::from_secs(6);
+
+/// Events the user can be notified about, as named in `config.toml`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationKind {
+    StabilizationSent,
+    StabilizationReceived,
+    ChannelReady,
+    ChannelClosed,
+    HighRisk,
+}
+
+impl NotificationKind {
+    pub const ALL: [NotificationKind; 5] = [
+        NotificationKind::StabilizationSent,
+        NotificationKind::StabilizationReceived,
+        NotificationKind::ChannelReady,
+        NotificationKind::ChannelClosed,
+        NotificationKind::HighRisk,
+    ];
+
+    fn is_warning(self) -> bool {
+        matches!(self, NotificationKind::ChannelClosed | NotificationKind::HighRisk)
+    }
+}
+
+struct Toast {
+    kind: NotificationKind,
+    message: String,
+    shown_at: Instant,
+}
+
+/// Shows in-app toasts and, if enabled, OS notifications for the events the
+/// user picked.
+pub struct Notifier {
+    enabled: Vec<NotificationKind>,
+    os_notifications: bool,
+    toasts: Vec<Toast>,
+}
+
+impl Notifier {
+    pub fn new(enabled: Vec<NotificationKind>, os_notifications: bool) -> Self {
+        Self {
+            enabled,
+            os_notifications,
+            toasts: Vec::new(),
+        }
+    }
+
+    pub fn notify(&mut self, kind: NotificationKind, message: String) {
+        println!("{}", message);
+        if !self.enabled.contains(&kind) {
+            return;
+        }
+
+        if self.os_notifications {
+            if let Err(e) = notify_rust::Notification::new()
+                .summary("Stable Channels")
+                .body(&message)
+                .show()
+            {
+                eprintln!("Failed to show notification: {}", e);
+            }
+        }
+
+        self.toasts.push(Toast {
+            kind,
+            message,
+            shown_at: Instant::now(),
+        });
+    }
+
+    /// Draws the current toasts in the bottom right corner, newest last.
+    pub fn show(&mut self, ctx: &egui::Context) {
+        self.toasts.retain(|toast| toast.shown_at.elapsed() < TOAST_DURATION);
+        if self.toasts.is_empty() {
+            return;
+        }
+
+        egui::Area::new(egui::Id::new("toasts"))
+            .anchor(egui::Align2::RIGHT_BOTTOM, egui::vec2(-16.0, -16.0))
+            .order(egui::Order::Foreground)
+            .show(ctx, |ui| {
+                let mut dismissed = None;
+                for (i, toast) in self.toasts.iter().enumerate() {
+                    let stroke = if toast.kind.is_warning() {
+                        Color32::LIGHT_RED
+                    } else {
+                        Color32::from_rgb(247, 147, 26)
+                    };
+                    egui::Frame::popup(ui.style())
+                        .stroke(egui::Stroke::new(1.0, stroke))
+                        .show(ui, |ui| {
+                            ui.set_max_width(280.0);
+                            ui.horizontal(|ui| {
+                                ui.label(&toast.message);
+                                if ui.small_button("✕").clicked() {
+                                    dismissed = Some(i);
+                                }
+                            });
+                        });
+                    ui.add_space(6.0);
+                }
+                if let Some(i) = dismissed {
+                    self.toasts.remove(i);
+                }
+            });
+
+        ctx.request_repaint_after(Duration::from_secs(1));
+    }
+}
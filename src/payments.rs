@@ -0,0 +1,227 @@
+use std::str::FromStr;
+
+use ldk_node::bitcoin::{bech32, Address};
+use ldk_node::lightning::offers::offer::{Amount, Offer};
+use ldk_node::lightning_invoice::Bolt11Invoice;
+use ldk_node::lightning::ln::channelmanager::PaymentId;
+use ldk_node::Node;
+use serde_json::Value;
+use ureq::Agent;
+
+/// Something the user pasted into the Send screen.
+pub enum PaymentRequest {
+    Bolt11(Bolt11Invoice),
+    Bolt12(Offer),
+    LnUrlPay(LnUrlPay),
+}
+
+/// The `payRequest` parameters served by an LNURL-pay endpoint.
+pub struct LnUrlPay {
+    pub callback: String,
+    pub min_sendable_msat: u64,
+    pub max_sendable_msat: u64,
+    pub description: String,
+}
+
+impl PaymentRequest {
+    /// Amount fixed by the request itself, if any.
+    pub fn amount_msat(&self) -> Option<u64> {
+        match self {
+            PaymentRequest::Bolt11(invoice) => invoice.amount_milli_satoshis(),
+            PaymentRequest::Bolt12(offer) => match offer.amount() {
+                Some(Amount::Bitcoin { amount_msats }) => Some(amount_msats),
+                _ => None,
+            },
+            PaymentRequest::LnUrlPay(lnurl) if lnurl.min_sendable_msat == lnurl.max_sendable_msat => {
+                Some(lnurl.min_sendable_msat)
+            }
+            PaymentRequest::LnUrlPay(_) => None,
+        }
+    }
+
+    pub fn description(&self) -> String {
+        match self {
+            PaymentRequest::Bolt11(invoice) => invoice.description().to_string(),
+            PaymentRequest::Bolt12(offer) => offer
+                .description()
+                .map(|d| d.to_string())
+                .unwrap_or_default(),
+            PaymentRequest::LnUrlPay(lnurl) => lnurl.description.clone(),
+        }
+    }
+}
+
+/// Builds a BIP21 URI paying `amount_sats` to `address`, with optional
+/// Lightning invoice and offer so Lightning wallets can pay instantly.
+pub fn bip21_uri(
+    address: &Address,
+    amount_sats: u64,
+    invoice: Option<&str>,
+    offer: Option<&str>,
+) -> String {
+    let btc = format!("{}.{:08}", amount_sats / 100_000_000, amount_sats % 100_000_000);
+    let btc = btc.trim_end_matches('0').trim_end_matches('.');
+
+    let mut uri = format!("bitcoin:{}?amount={}", address, btc);
+    if let Some(invoice) = invoice {
+        uri.push_str(&format!("&lightning={}", invoice));
+    }
+    if let Some(offer) = offer {
+        uri.push_str(&format!("&lno={}", offer));
+    }
+    uri
+}
+
+/// Parses a Bolt11 invoice, Bolt12 offer, LNURL or Lightning address.
+pub fn parse_payment_request(agent: &Agent, input: &str) -> Result<PaymentRequest, String> {
+    let input = input.trim();
+    let input = input
+        .strip_prefix("lightning:")
+        .or_else(|| input.strip_prefix("LIGHTNING:"))
+        .unwrap_or(input);
+    let lower = input.to_lowercase();
+
+    if lower.starts_with("lnurl") {
+        let url = decode_lnurl(&lower)?;
+        return fetch_lnurl_pay(agent, &url).map(PaymentRequest::LnUrlPay);
+    }
+
+    if let Some((user, domain)) = input.split_once('@') {
+        let url = format!("https://{}/.well-known/lnurlp/{}", domain, user);
+        return fetch_lnurl_pay(agent, &url).map(PaymentRequest::LnUrlPay);
+    }
+
+    if lower.starts_with("lno") {
+        return Offer::from_str(input)
+            .map(PaymentRequest::Bolt12)
+            .map_err(|e| format!("Invalid Bolt12 offer: {:?}", e));
+    }
+
+    Bolt11Invoice::from_str(input)
+        .map(PaymentRequest::Bolt11)
+        .map_err(|e| format!("Invalid invoice: {}", e))
+}
+
+/// Pays `request`, using `amount_msat` when the request doesn't fix one.
+pub fn pay(
+    node: &Node,
+    agent: &Agent,
+    request: &PaymentRequest,
+    amount_msat: u64,
+) -> Result<PaymentId, String> {
+    match request {
+        PaymentRequest::Bolt11(invoice) => {
+            if invoice.amount_milli_satoshis().is_some() {
+                node.bolt11_payment().send(invoice, None)
+            } else {
+                node.bolt11_payment().send_using_amount(invoice, amount_msat, None)
+            }
+            .map_err(|e| format!("Payment failed: {}", e))
+        }
+        PaymentRequest::Bolt12(offer) => {
+            if offer.amount().is_some() {
+                node.bolt12_payment().send(offer, None, None)
+            } else {
+                node.bolt12_payment().send_using_amount(offer, amount_msat, None, None)
+            }
+            .map_err(|e| format!("Payment failed: {}", e))
+        }
+        PaymentRequest::LnUrlPay(lnurl) => {
+            let invoice = fetch_lnurl_invoice(agent, lnurl, amount_msat)?;
+            node.bolt11_payment()
+                .send(&invoice, None)
+                .map_err(|e| format!("Payment failed: {}", e))
+        }
+    }
+}
+
+fn decode_lnurl(lnurl: &str) -> Result<String, String> {
+    let (_, data) = bech32::decode(lnurl).map_err(|e| format!("Invalid LNURL: {}", e))?;
+    String::from_utf8(data).map_err(|_| "Invalid LNURL: not a URL".to_string())
+}
+
+fn fetch_lnurl_pay(agent: &Agent, url: &str) -> Result<LnUrlPay, String> {
+    let json: Value = agent
+        .get(url)
+        .call()
+        .map_err(|e| format!("LNURL request failed: {}", e))?
+        .into_json()
+        .map_err(|e| format!("Invalid LNURL response: {}", e))?;
+
+    if let Some(reason) = json.get("reason").and_then(|r| r.as_str()) {
+        return Err(format!("LNURL error: {}", reason));
+    }
+    if json.get("tag").and_then(|t| t.as_str()) != Some("payRequest") {
+        return Err("Only LNURL-pay is supported".to_string());
+    }
+
+    let callback = json
+        .get("callback")
+        .and_then(|c| c.as_str())
+        .ok_or("LNURL response has no callback")?;
+    let min_sendable_msat = json.get("minSendable").and_then(|v| v.as_u64()).unwrap_or(1_000);
+    let max_sendable_msat = json
+        .get("maxSendable")
+        .and_then(|v| v.as_u64())
+        .unwrap_or(min_sendable_msat);
+
+    // The description lives in the metadata as a list of [mime type, content] pairs.
+    let description = json
+        .get("metadata")
+        .and_then(|m| m.as_str())
+        .and_then(|m| serde_json::from_str::<Vec<Vec<String>>>(m).ok())
+        .and_then(|entries| {
+            entries
+                .into_iter()
+                .find(|e| e.len() == 2 && e[0] == "text/plain")
+                .map(|e| e[1].clone())
+        })
+        .unwrap_or_default();
+
+    Ok(LnUrlPay {
+        callback: callback.to_string(),
+        min_sendable_msat,
+        max_sendable_msat,
+        description,
+    })
+}
+
+fn fetch_lnurl_invoice(
+    agent: &Agent,
+    lnurl: &LnUrlPay,
+    amount_msat: u64,
+) -> Result<Bolt11Invoice, String> {
+    if amount_msat < lnurl.min_sendable_msat || amount_msat > lnurl.max_sendable_msat {
+        return Err(format!(
+            "Amount must be between {} and {} sats",
+            lnurl.min_sendable_msat / 1000,
+            lnurl.max_sendable_msat / 1000
+        ));
+    }
+
+    let separator = if lnurl.callback.contains('?') { '&' } else { '?' };
+    let url = format!("{}{}amount={}", lnurl.callback, separator, amount_msat);
+    let json: Value = agent
+        .get(&url)
+        .call()
+        .map_err(|e| format!("LNURL invoice request failed: {}", e))?
+        .into_json()
+        .map_err(|e| format!("Invalid LNURL invoice response: {}", e))?;
+
+    if let Some(reason) = json.get("reason").and_then(|r| r.as_str()) {
+        return Err(format!("LNURL error: {}", reason));
+    }
+
+    let pr = json
+        .get("pr")
+        .and_then(|p| p.as_str())
+        .ok_or("LNURL response has no invoice")?;
+    let invoice = Bolt11Invoice::from_str(pr).map_err(|e| format!("Invalid invoice: {}", e))?;
+
+    // Never pay an invoice for more than we asked for.
+    if invoice.amount_milli_satoshis() != Some(amount_msat) {
+        return Err("LNURL service returned an invoice for the wrong amount".to_string());
+    }
+
+    Ok(invoice)
+}
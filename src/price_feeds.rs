@@ -1,22 +1,80 @@
 use ureq::Agent;
-use serde_json::Value;
-use std::error::Error;
-use retry::{retry, delay::Fixed};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use retry::{retry, delay::Fixed, OperationResult};
+
+use crate::config::{FeedConfig, FeedFormat};
+use crate::price_sources::{Attestation, Csv, IndexedJsonPath, JsonPath, PriceSource, SignedOracle};
+use crate::price_stream::{spawn_stream, FeedStream, LivePrices};
+use crate::recovery::unix_now;
+
+/// How long a fetched median is served before the feeds are asked again.
+const CACHE_TTL: Duration = Duration::from_secs(15);
+
+/// Shortest time between two requests to the same feed, unless the feed
+/// sets its own.
+const DEFAULT_MIN_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How long to leave a feed alone after a 429 without a `Retry-After`.
+const DEFAULT_BACKOFF: Duration = Duration::from_secs(60);
 
 pub struct PriceFeed {
     pub name: String,
     pub urlformat: String,
-    pub jsonpath: Vec<String>,
+    /// Reads the price out of the response.
+    pub source: Box<dyn PriceSource>,
+    /// Shortest time between two requests to this feed.
+    pub min_interval: Duration,
+    /// Ticker WebSocket used instead of polling in streaming mode.
+    pub stream: Option<FeedStream>,
 }
 
 impl PriceFeed {
     pub fn new(name: &str, urlformat: &str, jsonpath: Vec<&str>) -> PriceFeed {
+        let jsonpath = JsonPath(jsonpath.iter().map(|&s| s.to_string()).collect());
+        PriceFeed::with_source(name, urlformat, Box::new(jsonpath))
+    }
+
+    pub fn with_source(name: &str, urlformat: &str, source: Box<dyn PriceSource>) -> PriceFeed {
         PriceFeed {
             name: name.to_string(),
             urlformat: urlformat.to_string(),
-            jsonpath: jsonpath.iter().map(|&s| s.to_string()).collect(),
+            source,
+            min_interval: DEFAULT_MIN_INTERVAL,
+            stream: None,
         }
     }
+
+    /// A feed added in `config.toml`.
+    pub fn from_config(config: &FeedConfig) -> Result<PriceFeed, String> {
+        let source: Box<dyn PriceSource> = match &config.format {
+            FeedFormat::Json { path } => Box::new(IndexedJsonPath::parse(path)?),
+            FeedFormat::Csv { column } => Box::new(Csv {
+                column: column.clone(),
+            }),
+            FeedFormat::Oracle {
+                pubkey,
+                max_age_secs,
+            } => Box::new(SignedOracle::new(pubkey, *max_age_secs)?),
+        };
+        Ok(PriceFeed::with_source(&config.name, &config.url, source))
+    }
+
+    pub fn with_min_interval(mut self, min_interval: Duration) -> PriceFeed {
+        self.min_interval = min_interval;
+        self
+    }
+
+    pub fn with_stream(mut self, url: &str, subscribe: &str, jsonpath: Vec<&str>) -> PriceFeed {
+        self.stream = Some(FeedStream {
+            url: url.to_string(),
+            subscribe: subscribe.to_string(),
+            jsonpath: jsonpath.iter().map(|&s| s.to_string()).collect(),
+        });
+        self
+    }
 }
 
 pub fn set_price_feeds() -> Vec<PriceFeed> {
@@ -25,12 +83,19 @@ pub fn set_price_feeds() -> Vec<PriceFeed> {
             "Bitstamp",
             "https://www.bitstamp.net/api/v2/ticker/btcusd/",
             vec!["last"],
+        )
+        .with_stream(
+            "wss://ws.bitstamp.net",
+            r#"{"event":"bts:subscribe","data":{"channel":"live_trades_btcusd"}}"#,
+            vec!["data", "price"],
         ),
         PriceFeed::new(
             "CoinGecko",
             "https://api.coingecko.com/api/v3/simple/price?ids=bitcoin&vs_currencies=usd",
             vec!["bitcoin", "usd"],
-        ),
+        )
+        // The free API allows only a handful of calls a minute.
+        .with_min_interval(Duration::from_secs(30)),
         PriceFeed::new(
             "Coindesk",
             "https://api.coindesk.com/v1/bpi/currentprice/USD.json",
@@ -40,6 +105,11 @@ pub fn set_price_feeds() -> Vec<PriceFeed> {
             "Coinbase",
             "https://api.coinbase.com/v2/prices/spot?currency=USD",
             vec!["data", "amount"],
+        )
+        .with_stream(
+            "wss://ws-feed.exchange.coinbase.com",
+            r#"{"type":"subscribe","product_ids":["BTC-USD"],"channels":["ticker"]}"#,
+            vec!["price"],
         ),
         PriceFeed::new(
             "Blockchain.com",
@@ -49,74 +119,363 @@ pub fn set_price_feeds() -> Vec<PriceFeed> {
     ]
 }
 
-pub fn fetch_prices(
-    agent: &Agent,
-    price_feeds: &[PriceFeed],
-) -> Result<Vec<(String, f64)>, Box<dyn Error>> {
-    let mut prices = Vec::new();
-
-    for price_feed in price_feeds {
-        let url: String = price_feed
-            .urlformat
-            .replace("{currency_lc}", "usd")
-            .replace("{currency}", "USD");
-
-        let response = retry(Fixed::from_millis(300).take(3), || {
-            match agent.get(&url).call() {
-                Ok(resp) => {
-                    if resp.status() >= 200 && resp.status() < 300 {
-                        Ok(resp)
-                    } else {
-                        Err(format!("Received status code: {}", resp.status()))
-                    }
+/// Quotes further than this from the median of all quotes are left out of the
+/// final median, so one broken feed can't move the price.
+const OUTLIER_THRESHOLD: f64 = 0.02;
+
+/// What one price feed returned on the last fetch.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FeedQuote {
+    pub name: String,
+    pub price: Option<f64>,
+    pub latency_ms: u64,
+    pub error: Option<String>,
+    /// Left out of the median as an outlier.
+    pub excluded: bool,
+    /// Streamed rather than polled.
+    #[serde(default)]
+    pub live: bool,
+    /// Reused from an earlier fetch because the feed can't be asked again yet.
+    #[serde(default)]
+    pub stale: bool,
+    /// Set when the feed is an oracle that signed the price.
+    #[serde(default)]
+    pub attestation: Option<Attestation>,
+}
+
+struct FetchError {
+    message: String,
+    /// Set when the feed told us to back off.
+    retry_after: Option<Duration>,
+}
+
+impl From<String> for FetchError {
+    fn from(message: String) -> Self {
+        FetchError {
+            message,
+            retry_after: None,
+        }
+    }
+}
+
+fn fetch_price(agent: &Agent, price_feed: &PriceFeed) -> Result<(f64, Option<Attestation>), FetchError> {
+    let url: String = price_feed
+        .urlformat
+        .replace("{currency_lc}", "usd")
+        .replace("{currency}", "USD");
+
+    let mut backoff = None;
+    let response = retry(Fixed::from_millis(300).take(3), || {
+        match agent.get(&url).call() {
+            Ok(resp) => OperationResult::Ok(resp),
+            // Retrying a rate limited request only makes it worse.
+            Err(ureq::Error::Status(429, resp)) => {
+                backoff = Some(retry_after(&resp).unwrap_or(DEFAULT_BACKOFF));
+                OperationResult::Err("Rate limited (429)".to_string())
+            }
+            Err(ureq::Error::Status(code, _)) => {
+                OperationResult::Retry(format!("Received status code: {}", code))
+            }
+            Err(e) => OperationResult::Retry(e.to_string()),
+        }
+    })
+    .map_err(|e| FetchError {
+        message: e.to_string(),
+        retry_after: backoff,
+    })?;
+
+    let body = response.into_string().map_err(|e| e.to_string())?;
+    Ok(price_feed.source.parse_attested(&body)?)
+}
+
+/// The `Retry-After` header in seconds. HTTP dates aren't used by any of
+/// our feeds.
+fn retry_after(resp: &ureq::Response) -> Option<Duration> {
+    resp.header("Retry-After")?
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .map(Duration::from_secs)
+}
+
+/// The quote from one feed, and how long it asked us to back off, if it did.
+fn fetch_quote(agent: &Agent, price_feed: &PriceFeed) -> (FeedQuote, Option<Duration>) {
+    let started = Instant::now();
+    let result = fetch_price(agent, price_feed);
+    let latency_ms = started.elapsed().as_millis() as u64;
+    match result {
+        Ok((price, attestation)) => (
+            FeedQuote {
+                name: price_feed.name.clone(),
+                price: Some(price),
+                latency_ms,
+                error: None,
+                excluded: false,
+                live: false,
+                stale: false,
+                attestation,
+            },
+            None,
+        ),
+        Err(e) => {
+            println!("Failed to fetch price from {}: {}", price_feed.name, e.message);
+            (
+                FeedQuote {
+                    name: price_feed.name.clone(),
+                    price: None,
+                    latency_ms,
+                    error: Some(e.message),
+                    excluded: false,
+                    live: false,
+                    stale: false,
+                    attestation: None,
+                },
+                e.retry_after,
+            )
+        }
+    }
+}
+
+/// The median and the quotes it was taken from, as of `fetched_at`.
+#[derive(Debug, Clone, Default)]
+pub struct PriceSnapshot {
+    pub quotes: Vec<FeedQuote>,
+    pub median: Option<f64>,
+    /// Unix time of the fetch.
+    pub fetched_at: i64,
+}
+
+#[derive(Default)]
+struct FeedState {
+    last_request: Option<Instant>,
+    blocked_until: Option<Instant>,
+    /// The last good quote and when it was fetched.
+    last_quote: Option<(Instant, FeedQuote)>,
+}
+
+#[derive(Default)]
+struct ServiceState {
+    snapshot: Option<(Instant, PriceSnapshot)>,
+    feeds: HashMap<String, FeedState>,
+}
+
+/// Fetches prices on behalf of every stability check, so several checks in a
+/// row cost one round of requests. Feeds asked too recently, or that asked us
+/// to back off, keep their previous quote until they may be asked again.
+///
+/// In streaming mode feeds with a ticker WebSocket are only polled while
+/// their stream is stale, and the median is retaken from the latest ticks on
+/// every call.
+pub struct PriceService {
+    agent: Agent,
+    feeds: Vec<PriceFeed>,
+    state: Mutex<ServiceState>,
+    live: LivePrices,
+    /// Set once streaming has started: how old a streamed price may get
+    /// before the feed is polled again.
+    stream_max_age: OnceLock<Duration>,
+}
+
+static PRICE_SERVICE: OnceLock<PriceService> = OnceLock::new();
+
+/// The price service shared by the whole app. Panics before
+/// [`init_price_service`], rather than fetching prices around the proxy.
+pub fn price_service() -> &'static PriceService {
+    PRICE_SERVICE
+        .get()
+        .expect("Price service used before init_price_service")
+}
+
+/// Sets up the shared price service with the built-in feeds plus the ones
+/// from `config.toml`, fetching through `agent`. Must run before the first
+/// price lookup; feeds that don't parse are skipped.
+pub fn init_price_service(agent: Agent, custom_feeds: &[FeedConfig]) {
+    let mut feeds = set_price_feeds();
+    for config in custom_feeds {
+        match PriceFeed::from_config(config) {
+            Ok(feed) => feeds.push(feed),
+            Err(e) => eprintln!("Skipping price feed {}: {}", config.name, e),
+        }
+    }
+    if PRICE_SERVICE.set(PriceService::new(agent, feeds)).is_err() {
+        eprintln!("Price service already running, custom feeds not added");
+    }
+}
+
+impl PriceService {
+    pub fn new(agent: Agent, feeds: Vec<PriceFeed>) -> Self {
+        Self {
+            agent,
+            feeds,
+            state: Mutex::new(ServiceState::default()),
+            live: LivePrices::default(),
+            stream_max_age: OnceLock::new(),
+        }
+    }
+
+    /// Subscribes to the ticker of every feed that has one. Calling it again
+    /// does nothing.
+    pub fn start_streaming(&'static self, max_age: Duration) {
+        if self.stream_max_age.set(max_age).is_err() {
+            return;
+        }
+        for price_feed in &self.feeds {
+            if let Some(stream) = &price_feed.stream {
+                spawn_stream(&price_feed.name, stream, &self.live);
+            }
+        }
+    }
+
+    /// The latest prices, with streamed ones taking the place of polled
+    /// quotes wherever the stream is fresh.
+    pub fn latest(&self) -> PriceSnapshot {
+        let mut snapshot = self.polled();
+        if self.stream_max_age.get().is_some() {
+            for quote in snapshot.quotes.iter_mut() {
+                if let Some(live) = self.live_quote(&quote.name) {
+                    *quote = live;
                 }
-                Err(e) => Err(e.to_string()),
             }
+            snapshot.median = median_of_quotes(&mut snapshot.quotes);
+            snapshot.fetched_at = unix_now();
+        }
+        snapshot
+    }
+
+    fn live_quote(&self, name: &str) -> Option<FeedQuote> {
+        let max_age = *self.stream_max_age.get()?;
+        let price = self.live.fresh(name, max_age)?;
+        Some(FeedQuote {
+            name: name.to_string(),
+            price: Some(price),
+            latency_ms: 0,
+            error: None,
+            excluded: false,
+            live: true,
+            stale: false,
+            attestation: None,
         })
-        .map_err(|e| -> Box<dyn Error> {
-            Box::new(std::io::Error::new(std::io::ErrorKind::Other, e.to_string()))
-        })?;
-
-        let json: Value = response.into_json()?;
-        let mut data = &json;
-
-        for key in &price_feed.jsonpath {
-            if let Some(inner_data) = data.get(key) {
-                data = inner_data;
-            } else {
-                println!(
-                    "Key '{}' not found in the response from {}",
-                    key, price_feed.name
-                );
-                continue;
+    }
+
+    /// The cached polled prices if younger than `CACHE_TTL`, otherwise fresh
+    /// ones. Callers arriving during a fetch wait for it rather than starting
+    /// their own.
+    fn polled(&self) -> PriceSnapshot {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some((fetched, snapshot)) = &state.snapshot {
+            if fetched.elapsed() < CACHE_TTL {
+                return snapshot.clone();
             }
         }
 
-        if let Some(price) = data.as_f64() {
-            prices.push((price_feed.name.clone(), price));
-        } else if let Some(price_str) = data.as_str() {
-            if let Ok(price) = price_str.parse::<f64>() {
-                prices.push((price_feed.name.clone(), price));
-            } else {
-                println!("Invalid price format for {}: {}", price_feed.name, price_str);
+        let now = Instant::now();
+        let mut quotes = Vec::with_capacity(self.feeds.len());
+        for price_feed in &self.feeds {
+            // Streamed prices are only taken in latest(), so a stream that
+            // goes quiet can't live on in the cache. If it does, this stands
+            // in until the feed is polled again.
+            if self.live_quote(&price_feed.name).is_some() {
+                quotes.push(FeedQuote {
+                    name: price_feed.name.clone(),
+                    price: None,
+                    latency_ms: 0,
+                    error: Some("Price stream stopped, polling again shortly".to_string()),
+                    excluded: false,
+                    live: true,
+                    stale: false,
+                    attestation: None,
+                });
+                continue;
+            }
+            let feed = state.feeds.entry(price_feed.name.clone()).or_default();
+            let blocked = feed.blocked_until.is_some_and(|until| now < until);
+            let too_soon = feed
+                .last_request
+                .is_some_and(|last| now.duration_since(last) < price_feed.min_interval);
+
+            if blocked || too_soon {
+                // Only reuse a quote that is about as recent as the feed
+                // could have given, so an old one never passes for current.
+                let max_age = price_feed.min_interval + CACHE_TTL;
+                let reused = feed
+                    .last_quote
+                    .as_ref()
+                    .filter(|(fetched, _)| now.duration_since(*fetched) <= max_age)
+                    .filter(|(_, quote)| match (&quote.attestation, price_feed.source.max_age_secs()) {
+                        (Some(attestation), Some(max_age_secs)) => unix_now() - attestation.timestamp <= max_age_secs,
+                        _ => true,
+                    })
+                    .map(|(_, quote)| FeedQuote {
+                        stale: true,
+                        ..quote.clone()
+                    });
+                quotes.push(reused.unwrap_or_else(|| FeedQuote {
+                    name: price_feed.name.clone(),
+                    price: None,
+                    latency_ms: 0,
+                    error: Some("Rate limited, waiting before asking again".to_string()),
+                    excluded: false,
+                    live: false,
+                    stale: false,
+                    attestation: None,
+                }));
+                continue;
             }
-        } else {
-            println!(
-                "Price data not found or invalid format for {}",
-                price_feed.name
-            );
+
+            let (quote, retry_after) = fetch_quote(&self.agent, price_feed);
+            feed.last_request = Some(Instant::now());
+            feed.blocked_until = retry_after.map(|delay| Instant::now() + delay);
+            if quote.price.is_some() {
+                feed.last_quote = Some((Instant::now(), quote.clone()));
+            }
+            quotes.push(quote);
         }
+
+        let median = median_of_quotes(&mut quotes);
+        let snapshot = PriceSnapshot {
+            quotes,
+            median,
+            fetched_at: unix_now(),
+        };
+        state.snapshot = Some((Instant::now(), snapshot.clone()));
+        snapshot
     }
+}
 
-    if prices.len() < 5 {
-        println!("Fewer than 5 prices fetched.");
+/// Median of the quotes after dropping outliers, which get marked `excluded`.
+pub fn median_of_quotes(quotes: &mut [FeedQuote]) -> Option<f64> {
+    let mut values: Vec<f64> = quotes.iter().filter_map(|q| q.price).collect();
+    if values.is_empty() {
+        return None;
     }
+    let overall = median(&mut values);
 
-    if prices.is_empty() {
-        return Err("No valid prices fetched.".into());
+    for quote in quotes.iter_mut() {
+        quote.excluded = quote
+            .price
+            .is_some_and(|price| ((price - overall) / overall).abs() > OUTLIER_THRESHOLD);
+    }
+    // With only a couple of feeds that disagree there is no majority to
+    // trust, so keep them all.
+    if quotes.iter().all(|q| q.price.is_none() || q.excluded) {
+        quotes.iter_mut().for_each(|q| q.excluded = false);
     }
 
-    Ok(prices)
+    let kept: Vec<(String, f64)> = quotes
+        .iter()
+        .filter(|q| !q.excluded)
+        .filter_map(|q| q.price.map(|price| (q.name.clone(), price)))
+        .collect();
+    calculate_median_price(kept).ok()
+}
+
+fn median(values: &mut [f64]) -> f64 {
+    values.sort_by(|a, b| a.total_cmp(b));
+    if values.len() % 2 == 0 {
+        (values[values.len() / 2 - 1] + values[values.len() / 2]) / 2.0
+    } else {
+        values[values.len() / 2]
+    }
 }
 
 pub fn calculate_median_price(
@@ -129,12 +488,7 @@ pub fn calculate_median_price(
 
     // Calculate the median price
     let mut price_values: Vec<f64> = prices.iter().map(|(_, price)| *price).collect();
-    price_values.sort_by(|a, b| a.partial_cmp(b).unwrap());
-    let median_price = if price_values.len() % 2 == 0 {
-        (price_values[price_values.len() / 2 - 1] + price_values[price_values.len() / 2]) / 2.0
-    } else {
-        price_values[price_values.len() / 2]
-    };
+    let median_price = median(&mut price_values);
 
     println!("\nMedian BTC/USD price:     ${:.2}\n", median_price);
 
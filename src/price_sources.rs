@@ -0,0 +1,429 @@
+use std::str::FromStr;
+
+use ldk_node::bitcoin::hashes::{sha256, Hash};
+use ldk_node::bitcoin::secp256k1::{schnorr, Message, Secp256k1, XOnlyPublicKey};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::recovery::unix_now;
+
+/// How far ahead of our clock an oracle's timestamp may be.
+const MAX_CLOCK_SKEW_SECS: i64 = 60;
+
+/// Turns the body of a price feed's response into a BTC/USD price.
+pub trait PriceSource: Send + Sync {
+    fn parse_price(&self, body: &str) -> Result<f64, String>;
+
+    /// The price along with the oracle's signature over it, for sources
+    /// that sign what they report.
+    fn parse_attested(&self, body: &str) -> Result<(f64, Option<Attestation>), String> {
+        self.parse_price(body).map(|price| (price, None))
+    }
+
+    /// How old an attested price may be before it is refused, for sources
+    /// that timestamp what they report.
+    fn max_age_secs(&self) -> Option<i64> {
+        None
+    }
+}
+
+/// Object keys leading to the price in a JSON response.
+pub struct JsonPath(pub Vec<String>);
+
+impl PriceSource for JsonPath {
+    fn parse_price(&self, body: &str) -> Result<f64, String> {
+        let json: Value = serde_json::from_str(body).map_err(|e| e.to_string())?;
+        let mut data = &json;
+        for key in &self.0 {
+            data = data
+                .get(key)
+                .ok_or_else(|| format!("Key '{}' not found in the response", key))?;
+        }
+        json_price(data)
+    }
+}
+
+enum PathStep {
+    Key(String),
+    Index(usize),
+}
+
+/// A JSON path that can also index into arrays, written like
+/// `result.XXBTZUSD.c[0]`.
+pub struct IndexedJsonPath(Vec<PathStep>);
+
+impl IndexedJsonPath {
+    pub fn parse(path: &str) -> Result<Self, String> {
+        let mut steps = Vec::new();
+        for part in path.split('.') {
+            if part.is_empty() {
+                return Err(format!("Empty key in '{}'", path));
+            }
+            let (key, indices) = match part.split_once('[') {
+                Some((key, indices)) => (key, Some(indices)),
+                None => (part, None),
+            };
+            if !key.is_empty() {
+                steps.push(PathStep::Key(key.to_string()));
+            }
+            let Some(indices) = indices else {
+                continue;
+            };
+            let indices = indices
+                .strip_suffix(']')
+                .ok_or_else(|| format!("Unclosed array index in '{}'", path))?;
+            for index in indices.split("][") {
+                let index = index
+                    .parse()
+                    .map_err(|_| format!("Invalid array index '{}' in '{}'", index, path))?;
+                steps.push(PathStep::Index(index));
+            }
+        }
+        Ok(Self(steps))
+    }
+}
+
+impl PriceSource for IndexedJsonPath {
+    fn parse_price(&self, body: &str) -> Result<f64, String> {
+        let json: Value = serde_json::from_str(body).map_err(|e| e.to_string())?;
+        let mut data = &json;
+        for step in &self.0 {
+            data = match step {
+                PathStep::Key(key) => data
+                    .get(key)
+                    .ok_or_else(|| format!("Key '{}' not found in the response", key))?,
+                PathStep::Index(index) => data
+                    .get(index)
+                    .ok_or_else(|| format!("Index {} not found in the response", index))?,
+            };
+        }
+        json_price(data)
+    }
+}
+
+/// A CSV response with a header row. The price is read from `column` of the
+/// last row, as feeds list oldest first.
+pub struct Csv {
+    pub column: String,
+}
+
+impl PriceSource for Csv {
+    fn parse_price(&self, body: &str) -> Result<f64, String> {
+        let mut lines = body.lines().map(str::trim).filter(|line| !line.is_empty());
+        let header = lines.next().ok_or("Empty CSV response")?;
+        let column = split_csv(header)
+            .iter()
+            .position(|name| *name == self.column)
+            .ok_or_else(|| format!("Column '{}' not found in the response", self.column))?;
+        let row = lines.last().ok_or("CSV response has no rows")?;
+        let value = split_csv(row)
+            .into_iter()
+            .nth(column)
+            .ok_or_else(|| format!("Row has no column '{}'", self.column))?;
+        parse_price_str(&value)
+    }
+}
+
+/// The fields of a CSV line. Fields may be quoted to contain commas, with
+/// `""` standing for a quote inside them.
+fn split_csv(line: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field).trim().to_string()),
+            c => field.push(c),
+        }
+    }
+    fields.push(field.trim().to_string());
+    fields
+}
+
+/// A price oracle that signs what it reports. The response is
+/// `{"price": "67012.50", "timestamp": 1718000000, "signature": "<hex>"}`,
+/// where the signature is a BIP340 Schnorr signature over
+/// `SHA256("BTCUSD|<price>|<timestamp>")` with the price exactly as sent.
+pub struct SignedOracle {
+    pub pubkey: XOnlyPublicKey,
+    /// Attestations older than this are refused.
+    pub max_age_secs: i64,
+}
+
+impl SignedOracle {
+    pub fn new(pubkey: &str, max_age_secs: i64) -> Result<Self, String> {
+        let pubkey = XOnlyPublicKey::from_str(pubkey)
+            .map_err(|e| format!("Invalid oracle public key: {}", e))?;
+        Ok(Self {
+            pubkey,
+            max_age_secs,
+        })
+    }
+
+    /// Checks the signature and age of an oracle response.
+    fn verify(&self, body: &str) -> Result<(f64, Attestation), String> {
+        let json: Value = serde_json::from_str(body).map_err(|e| e.to_string())?;
+        let price = json
+            .get("price")
+            .and_then(|p| p.as_str())
+            .ok_or("Oracle response has no price")?;
+        let timestamp = json
+            .get("timestamp")
+            .and_then(|t| t.as_i64())
+            .ok_or("Oracle response has no timestamp")?;
+        let signature = json
+            .get("signature")
+            .and_then(|s| s.as_str())
+            .ok_or("Oracle response has no signature")?;
+
+        let attestation = Attestation {
+            oracle: self.pubkey.to_string(),
+            price: price.to_string(),
+            timestamp,
+            signature: signature.to_string(),
+        };
+        let price = attestation.verify()?;
+
+        let age = unix_now() - timestamp;
+        if age > self.max_age_secs {
+            return Err(format!("Oracle attestation is {}s old", age));
+        }
+        if age < -MAX_CLOCK_SKEW_SECS {
+            return Err(format!("Oracle attestation is {}s in the future", -age));
+        }
+        Ok((price, attestation))
+    }
+}
+
+impl PriceSource for SignedOracle {
+    fn parse_price(&self, body: &str) -> Result<f64, String> {
+        self.verify(body).map(|(price, _)| price)
+    }
+
+    fn parse_attested(&self, body: &str) -> Result<(f64, Option<Attestation>), String> {
+        self.verify(body).map(|(price, attestation)| (price, Some(attestation)))
+    }
+
+    fn max_age_secs(&self) -> Option<i64> {
+        Some(self.max_age_secs)
+    }
+}
+
+/// A price signed by an oracle. Kept locally with each stabilization
+/// payment, to show which price it settled at. The counterparty doesn't
+/// get it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Attestation {
+    /// The oracle's x-only public key, hex.
+    pub oracle: String,
+    /// The price exactly as signed.
+    pub price: String,
+    pub timestamp: i64,
+    /// BIP340 signature, hex.
+    pub signature: String,
+}
+
+impl Attestation {
+    /// Checks the signature, returning the attested price.
+    pub fn verify(&self) -> Result<f64, String> {
+        let pubkey = XOnlyPublicKey::from_str(&self.oracle)
+            .map_err(|e| format!("Invalid oracle public key: {}", e))?;
+        let sig_bytes = hex::decode(&self.signature).map_err(|_| "Signature is not hex")?;
+        let sig = schnorr::Signature::from_slice(&sig_bytes)
+            .map_err(|e| format!("Invalid signature: {}", e))?;
+        let digest = sha256::Hash::hash(attestation_message(&self.price, self.timestamp).as_bytes());
+        Secp256k1::verification_only()
+            .verify_schnorr(&sig, &Message::from_digest(digest.to_byte_array()), &pubkey)
+            .map_err(|_| "Oracle signature does not verify".to_string())?;
+
+        parse_price_str(&self.price)
+    }
+}
+
+/// What an oracle signs for a price reported at `timestamp`.
+fn attestation_message(price: &str, timestamp: i64) -> String {
+    format!("BTCUSD|{}|{}", price, timestamp)
+}
+
+fn json_price(data: &Value) -> Result<f64, String> {
+    if let Some(price) = data.as_f64() {
+        checked_price(price)
+    } else if let Some(price_str) = data.as_str() {
+        parse_price_str(price_str)
+    } else {
+        Err("Price data not found or invalid format".to_string())
+    }
+}
+
+fn parse_price_str(price: &str) -> Result<f64, String> {
+    price
+        .parse::<f64>()
+        .map_err(|_| format!("Invalid price format: {}", price))
+        .and_then(checked_price)
+}
+
+/// Rejects what parses as a number but can't be a price, like "NaN", "inf"
+/// or a negative value.
+fn checked_price(price: f64) -> Result<f64, String> {
+    if price.is_finite() && price > 0.0 {
+        Ok(price)
+    } else {
+        Err(format!("Invalid price: {}", price))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ldk_node::bitcoin::secp256k1::Keypair;
+
+    fn keypair() -> Keypair {
+        Keypair::from_seckey_slice(&Secp256k1::new(), &[7u8; 32]).unwrap()
+    }
+
+    fn sign(price: &str, timestamp: i64) -> Attestation {
+        let keypair = keypair();
+        let digest = sha256::Hash::hash(attestation_message(price, timestamp).as_bytes());
+        let signature = Secp256k1::new()
+            .sign_schnorr_no_aux_rand(&Message::from_digest(digest.to_byte_array()), &keypair);
+        Attestation {
+            oracle: keypair.x_only_public_key().0.to_string(),
+            price: price.to_string(),
+            timestamp,
+            signature: hex::encode(signature.serialize()),
+        }
+    }
+
+    #[test]
+    fn json_path() {
+        let cases: &[(&[&str], &str, Result<f64, ()>)] = &[
+            (&["last"], r#"{"last": "67012.50"}"#, Ok(67012.50)),
+            (&["bpi", "USD", "rate_float"], r#"{"bpi": {"USD": {"rate_float": 67012.5}}}"#, Ok(67012.50)),
+            (&["last"], r#"{"price": 1}"#, Err(())),
+            (&["last"], r#"{"last": "n/a"}"#, Err(())),
+            (&["last"], r#"{"last": [1]}"#, Err(())),
+            (&["last"], r#"{"last": "NaN"}"#, Err(())),
+            (&["last"], r#"{"last": "inf"}"#, Err(())),
+            (&["last"], r#"{"last": -67012.5}"#, Err(())),
+            (&["last"], r#"{"last": 0}"#, Err(())),
+            (&["last"], "not json", Err(())),
+        ];
+        for (path, body, expected) in cases {
+            let source = JsonPath(path.iter().map(|s| s.to_string()).collect());
+            assert_eq!(source.parse_price(body).map_err(|_| ()), *expected, "{:?} on {}", path, body);
+        }
+    }
+
+    #[test]
+    fn indexed_json_path() {
+        let cases: &[(&str, &str, Result<f64, ()>)] = &[
+            ("result.XXBTZUSD.c[0]", r#"{"result": {"XXBTZUSD": {"c": ["67012.5", "1"]}}}"#, Ok(67012.50)),
+            ("data[1].price", r#"{"data": [{"price": 1}, {"price": 2}]}"#, Ok(2.0)),
+            ("[0][1]", "[[1, 2]]", Ok(2.0)),
+            ("data[2]", r#"{"data": [1, 2]}"#, Err(())),
+            ("data.price", r#"{"data": {"amount": 1}}"#, Err(())),
+        ];
+        for (path, body, expected) in cases {
+            let source = IndexedJsonPath::parse(path).unwrap();
+            assert_eq!(source.parse_price(body).map_err(|_| ()), *expected, "{} on {}", path, body);
+        }
+
+        for invalid in ["a..b", ".a", "a.", "a[x]", "a[0", "a[", "a[]"] {
+            assert!(IndexedJsonPath::parse(invalid).is_err(), "{} should not parse", invalid);
+        }
+    }
+
+    #[test]
+    fn csv() {
+        let cases: &[(&str, &str, Result<f64, ()>)] = &[
+            ("close", "time,close\n1,67000\n2,67012.5\n", Ok(67012.50)),
+            ("close", "\"time\",\"close\"\r\n\"1\",\"67012.5\"\r\n", Ok(67012.50)),
+            ("close", "name,close\n\"Bitcoin, USD\",67012.5\n", Ok(67012.50)),
+            ("close", "name,note,close\nBTC,\"said \"\"hi, there\"\"\",67012.5\n", Ok(67012.50)),
+            ("close", "time,open\n1,67000\n", Err(())),
+            ("close", "time,close\n", Err(())),
+            ("close", "time,close\n1,NaN\n", Err(())),
+            ("close", "time,close\n1,-inf\n", Err(())),
+            ("close", "time,close\n1,-5\n", Err(())),
+            ("close", "time,close\n1\n", Err(())),
+            ("close", "", Err(())),
+        ];
+        for (column, body, expected) in cases {
+            let source = Csv {
+                column: column.to_string(),
+            };
+            assert_eq!(source.parse_price(body).map_err(|_| ()), *expected, "{:?}", body);
+        }
+    }
+
+    #[test]
+    fn signed_oracle() {
+        let oracle = SignedOracle::new(&keypair().x_only_public_key().0.to_string(), 60).unwrap();
+        let now = unix_now();
+        let signed = |price: &str, timestamp: i64| {
+            let attestation = sign(price, timestamp);
+            serde_json::json!({
+                "price": attestation.price,
+                "timestamp": attestation.timestamp,
+                "signature": attestation.signature,
+            })
+            .to_string()
+        };
+        // A valid signature over a different price.
+        let forged = serde_json::json!({
+            "price": "1.00",
+            "timestamp": now,
+            "signature": sign("67012.50", now).signature,
+        })
+        .to_string();
+        let cases: &[(String, Result<f64, ()>)] = &[
+            (signed("67012.50", now), Ok(67012.50)),
+            (signed("67012.50", now - 30), Ok(67012.50)),
+            (signed("67012.50", now + 30), Ok(67012.50)),
+            (signed("67012.50", now - 120), Err(())),
+            (signed("67012.50", now + 3600), Err(())),
+            (signed("abc", now), Err(())),
+            (signed("NaN", now), Err(())),
+            (signed("-67012.50", now), Err(())),
+            (format!(r#"{{"price": "1", "timestamp": {}}}"#, now), Err(())),
+            (forged, Err(())),
+            ("not json".to_string(), Err(())),
+        ];
+        for (body, expected) in cases {
+            assert_eq!(oracle.parse_price(body).map_err(|_| ()), *expected, "{}", body);
+        }
+    }
+
+    #[test]
+    fn signed_attestation_verifies() {
+        assert_eq!(sign("67012.50", 1_718_000_000).verify(), Ok(67012.50));
+    }
+
+    #[test]
+    fn tampered_price_fails() {
+        let mut attestation = sign("67012.50", 1_718_000_000);
+        attestation.price = "67012.51".to_string();
+        assert!(attestation.verify().is_err());
+    }
+
+    #[test]
+    fn tampered_timestamp_fails() {
+        let mut attestation = sign("67012.50", 1_718_000_000);
+        attestation.timestamp += 1;
+        assert!(attestation.verify().is_err());
+    }
+
+    #[test]
+    fn other_oracle_fails() {
+        let mut attestation = sign("67012.50", 1_718_000_000);
+        let other = Keypair::from_seckey_slice(&Secp256k1::new(), &[8u8; 32]).unwrap();
+        attestation.oracle = other.x_only_public_key().0.to_string();
+        assert!(attestation.verify().is_err());
+    }
+}
@@ -0,0 +1,113 @@
+use std::collections::HashMap;
+use std::net::TcpStream;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde_json::Value;
+use tungstenite::stream::MaybeTlsStream;
+use tungstenite::Message;
+
+/// Wait before reconnecting a dropped stream.
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// Exchanges send at least a heartbeat this often; a socket quiet for longer
+/// is assumed dead and reconnected.
+const READ_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// An exchange ticker WebSocket a price feed can stream from.
+pub struct FeedStream {
+    pub url: String,
+    /// Message sent after connecting to start the ticker.
+    pub subscribe: String,
+    /// Where the price is in each ticker message. Messages without it
+    /// (subscription acks, heartbeats) are skipped.
+    pub jsonpath: Vec<String>,
+}
+
+struct LiveQuote {
+    price: f64,
+    received: Instant,
+}
+
+/// The last streamed price of each feed.
+#[derive(Default)]
+pub struct LivePrices {
+    quotes: Mutex<HashMap<String, LiveQuote>>,
+}
+
+impl LivePrices {
+    /// Last streamed price of `name`, unless older than `max_age`.
+    pub fn fresh(&self, name: &str, max_age: Duration) -> Option<f64> {
+        let quotes = self.quotes.lock().unwrap_or_else(|e| e.into_inner());
+        quotes
+            .get(name)
+            .filter(|quote| quote.received.elapsed() <= max_age)
+            .map(|quote| quote.price)
+    }
+
+    fn set(&self, name: &str, price: f64) {
+        let mut quotes = self.quotes.lock().unwrap_or_else(|e| e.into_inner());
+        quotes.insert(
+            name.to_string(),
+            LiveQuote {
+                price,
+                received: Instant::now(),
+            },
+        );
+    }
+}
+
+/// Streams `name`'s ticker into `live` on a background thread, reconnecting
+/// whenever the connection drops.
+pub fn spawn_stream(name: &'static str, stream: &'static FeedStream, live: &'static LivePrices) {
+    thread::spawn(move || loop {
+        if let Err(e) = run_stream(name, stream, live) {
+            eprintln!("{} price stream disconnected: {}", name, e);
+        }
+        thread::sleep(RECONNECT_DELAY);
+    });
+}
+
+fn run_stream(name: &str, stream: &FeedStream, live: &LivePrices) -> Result<(), String> {
+    let (mut socket, _) = tungstenite::connect(stream.url.as_str()).map_err(|e| e.to_string())?;
+    set_read_timeout(socket.get_mut());
+    socket
+        .send(Message::text(stream.subscribe.clone()))
+        .map_err(|e| e.to_string())?;
+    println!("Streaming {} prices from {}", name, stream.url);
+
+    loop {
+        match socket.read().map_err(|e| e.to_string())? {
+            Message::Text(text) => {
+                if let Some(price) = parse_price(&text, &stream.jsonpath) {
+                    live.set(name, price);
+                }
+            }
+            Message::Close(_) => return Err("closed by the exchange".to_string()),
+            _ => {}
+        }
+    }
+}
+
+fn parse_price(text: &str, jsonpath: &[String]) -> Option<f64> {
+    let json: Value = serde_json::from_str(text).ok()?;
+    let mut data = &json;
+    for key in jsonpath {
+        data = data.get(key)?;
+    }
+    data.as_f64()
+        .or_else(|| data.as_str().and_then(|s| s.parse().ok()))
+        .filter(|price| *price > 0.0)
+}
+
+fn set_read_timeout(stream: &mut MaybeTlsStream<TcpStream>) {
+    let tcp = match stream {
+        MaybeTlsStream::Plain(tcp) => tcp,
+        MaybeTlsStream::Rustls(tls) => tls.get_mut(),
+        _ => return,
+    };
+    if let Err(e) = tcp.set_read_timeout(Some(READ_TIMEOUT)) {
+        eprintln!("Failed to set price stream timeout: {}", e);
+    }
+}
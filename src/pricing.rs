@@ -0,0 +1,199 @@
+use serde::{Deserialize, Serialize};
+
+/// Longest lookback a pricing mode may ask for.
+const MAX_LOOKBACK_SECS: i64 = 24 * 3600;
+
+/// Longest a sample stands for the price. Past it the price is unknown
+/// rather than unchanged, so the gap while the app was closed doesn't count.
+/// Samples are at most two minutes apart, the spacing of the saved history.
+const MAX_SAMPLE_HOLD_SECS: i64 = 5 * 60;
+
+/// Which price stabilization settles at. Both sides of the channel have to
+/// use the same mode, so it is part of the agreement sent to the
+/// counterparty, and fixed when the channel is opened.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum PricingMode {
+    /// The median of the latest quotes.
+    #[default]
+    Spot,
+    /// Time-weighted average of the median over the last `minutes`.
+    Twap { minutes: u32 },
+    /// Exponential moving average of the median with a time constant of
+    /// `minutes`.
+    Ema { minutes: u32 },
+}
+
+impl PricingMode {
+    /// How far back samples are needed.
+    pub fn lookback_secs(self) -> i64 {
+        let secs = match self {
+            PricingMode::Spot => 0,
+            PricingMode::Twap { minutes } => minutes as i64 * 60,
+            // Samples older than five time constants weigh less than 1%.
+            PricingMode::Ema { minutes } => 5 * minutes as i64 * 60,
+        };
+        secs.min(MAX_LOOKBACK_SECS)
+    }
+
+    /// The price from `samples` of `(unix time, median)`, oldest first. Until
+    /// a full window has been sampled the average covers what there is.
+    pub fn price(self, samples: &[(i64, f64)], now: i64) -> Option<f64> {
+        let (_, spot) = *samples.last()?;
+        match self {
+            PricingMode::Spot => Some(spot),
+            PricingMode::Twap { minutes } => Some(twap(samples, now, minutes as i64 * 60)),
+            PricingMode::Ema { minutes } => Some(ema(samples, minutes as f64 * 60.0)),
+        }
+    }
+
+    pub fn label(self) -> String {
+        match self {
+            PricingMode::Spot => "Spot median".to_string(),
+            PricingMode::Twap { minutes } => format!("TWAP {} min", minutes),
+            PricingMode::Ema { minutes } => format!("EMA {} min", minutes),
+        }
+    }
+}
+
+/// Each sample holds until the next one, or for `MAX_SAMPLE_HOLD_SECS` at
+/// most; the part of that span inside the window is its weight. With no
+/// weight at all, as right after a long gap, it is the latest sample.
+fn twap(samples: &[(i64, f64)], now: i64, window_secs: i64) -> f64 {
+    let start = now - window_secs;
+    let mut weighted = 0.0;
+    let mut total = 0.0;
+    for (i, (timestamp, price)) in samples.iter().enumerate() {
+        let next = samples.get(i + 1).map_or(now, |(next, _)| *next);
+        let until = next.min(timestamp + MAX_SAMPLE_HOLD_SECS);
+        let weight = (until - (*timestamp).max(start)).max(0) as f64;
+        weighted += price * weight;
+        total += weight;
+    }
+    if total > 0.0 {
+        weighted / total
+    } else {
+        samples.last().map_or(0.0, |(_, price)| *price)
+    }
+}
+
+/// Irregularly spaced samples, so each step decays by the time since the
+/// previous one.
+fn ema(samples: &[(i64, f64)], tau_secs: f64) -> f64 {
+    let mut iter = samples.iter();
+    let Some((mut last_time, mut average)) = iter.next().copied() else {
+        return 0.0;
+    };
+    for (timestamp, price) in iter {
+        let alpha = if tau_secs > 0.0 {
+            1.0 - (-((timestamp - last_time).max(0) as f64) / tau_secs).exp()
+        } else {
+            1.0
+        };
+        average += alpha * (price - average);
+        last_time = *timestamp;
+    }
+    average
+}
+
+/// Adds a sample and drops those `mode` no longer needs. The newest sample
+/// older than the window is kept, as the TWAP weighs it up to the first one
+/// inside.
+pub fn record_sample(samples: &mut Vec<(i64, f64)>, mode: PricingMode, timestamp: i64, price: f64) {
+    if samples.last().is_some_and(|(last, _)| *last >= timestamp) {
+        return;
+    }
+    samples.push((timestamp, price));
+    let start = timestamp - mode.lookback_secs();
+    let inside = samples.partition_point(|(t, _)| *t < start);
+    samples.drain(..inside.saturating_sub(1));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn twap_weighs_samples_by_how_long_they_held() {
+        let samples = [(0, 100.0), (60, 200.0), (240, 400.0)];
+        // 100 for 60s, 200 for 180s, 400 for 60s.
+        let expected = (100.0 * 60.0 + 200.0 * 180.0 + 400.0 * 60.0) / 300.0;
+        assert_eq!(twap(&samples, 300, 600), expected);
+    }
+
+    #[test]
+    fn twap_only_counts_the_window() {
+        let samples = [(0, 100.0), (200, 200.0)];
+        // The window starts at 100: 100 for 100s, 200 for 100s.
+        assert_eq!(twap(&samples, 300, 200), 150.0);
+    }
+
+    #[test]
+    fn twap_does_not_carry_a_sample_across_a_gap() {
+        let window = 15 * 60;
+        // Last sample before a three hour shutdown, then the first after it.
+        let samples = [(0, 100.0), (60, 100.0), (3 * 3600, 200.0)];
+        assert_eq!(twap(&samples, 3 * 3600, window), 200.0);
+        // A minute later the old price still has no weight.
+        let samples = [(0, 100.0), (60, 100.0), (3 * 3600, 200.0), (3 * 3600 + 60, 300.0)];
+        assert_eq!(twap(&samples, 3 * 3600 + 60, window), 200.0);
+    }
+
+    #[test]
+    fn twap_of_nothing_is_zero() {
+        assert_eq!(twap(&[], 100, 60), 0.0);
+    }
+
+    #[test]
+    fn ema_decays_by_elapsed_time() {
+        let tau = 60.0;
+        let samples = [(0, 100.0), (60, 200.0)];
+        let alpha = 1.0 - (-1.0f64).exp();
+        assert!((ema(&samples, tau) - (100.0 + alpha * 100.0)).abs() < 1e-9);
+        // A long gap makes the new price all but replace the old average.
+        let samples = [(0, 100.0), (3600, 200.0)];
+        assert!((ema(&samples, tau) - 200.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn ema_with_no_time_constant_is_the_latest_price() {
+        assert_eq!(ema(&[(0, 100.0), (10, 150.0)], 0.0), 150.0);
+        assert_eq!(ema(&[], 60.0), 0.0);
+    }
+
+    #[test]
+    fn spot_mode_uses_the_latest_sample() {
+        let samples = [(0, 100.0), (60, 200.0)];
+        assert_eq!(PricingMode::Spot.price(&samples, 60), Some(200.0));
+        assert_eq!(PricingMode::Spot.price(&[], 60), None);
+    }
+
+    #[test]
+    fn record_sample_keeps_one_sample_before_the_window() {
+        let mode = PricingMode::Twap { minutes: 1 };
+        let mut samples = Vec::new();
+        for t in [0, 30, 60, 90, 120] {
+            record_sample(&mut samples, mode, t, t as f64);
+        }
+        // The window starts at 60, so 60 itself is the first one inside and
+        // 30 the one kept before it.
+        assert_eq!(samples.iter().map(|(t, _)| *t).collect::<Vec<_>>(), vec![30, 60, 90, 120]);
+    }
+
+    #[test]
+    fn record_sample_ignores_out_of_order_samples() {
+        let mut samples = vec![(100, 1.0)];
+        record_sample(&mut samples, PricingMode::Spot, 100, 2.0);
+        record_sample(&mut samples, PricingMode::Spot, 50, 3.0);
+        assert_eq!(samples, vec![(100, 1.0)]);
+    }
+
+    #[test]
+    fn spot_mode_keeps_only_the_previous_sample() {
+        let mut samples = Vec::new();
+        for t in [10, 20, 30] {
+            record_sample(&mut samples, PricingMode::Spot, t, t as f64);
+        }
+        assert_eq!(samples, vec![(20, 20.0), (30, 30.0)]);
+    }
+}
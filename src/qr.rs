@@ -0,0 +1,209 @@
+use std::path::Path;
+
+use eframe::egui;
+use egui::{ColorImage, TextureHandle, TextureOptions};
+use image::{GrayImage, Luma};
+use qrcode::{Color, QrCode};
+
+/// Modules of white border around the code, as required by the QR spec.
+const QUIET_ZONE: usize = 4;
+
+/// Pixels per module when saving to PNG.
+const PNG_MODULE_PX: usize = 8;
+
+/// Rewrites `data` into the form that encodes into the smallest QR code.
+///
+/// Bech32 strings (Bolt11 invoices, Bolt12 offers, segwit addresses) are case
+/// insensitive, and uppercase lets the encoder use alphanumeric mode, which is
+/// much denser than byte mode. BIP21 URIs get their scheme, bech32 address and
+/// bech32 parameter values uppercased; parameter keys and everything else
+/// are left alone, as wallets match keys case-sensitively.
+pub fn qr_friendly(data: &str) -> String {
+    let lower = data.to_lowercase();
+
+    if lower.starts_with("bitcoin:") {
+        let (address, query) = match data["bitcoin:".len()..].split_once('?') {
+            Some((address, query)) => (address, Some(query)),
+            None => (&data["bitcoin:".len()..], None),
+        };
+
+        let mut uri = String::from("BITCOIN:");
+        uri.push_str(&uppercase_if_bech32(address));
+        if let Some(query) = query {
+            let params: Vec<String> = query
+                .split('&')
+                .map(|param| match param.split_once('=') {
+                    Some((key, value)) => format!("{}={}", key, uppercase_if_bech32(value)),
+                    None => param.to_string(),
+                })
+                .collect();
+            uri.push('?');
+            uri.push_str(&params.join("&"));
+        }
+        return uri;
+    }
+
+    if lower.starts_with("lightning:") {
+        return data.to_uppercase();
+    }
+
+    uppercase_if_bech32(data)
+}
+
+fn uppercase_if_bech32(data: &str) -> String {
+    let lower = data.to_lowercase();
+    let is_bech32 = ["lnbc", "lntb", "lno", "bc1", "tb1", "bcrt1"]
+        .iter()
+        .any(|prefix| lower.starts_with(prefix));
+    if is_bech32 {
+        data.to_uppercase()
+    } else {
+        data.to_string()
+    }
+}
+
+fn encode(data: &str) -> Result<QrCode, String> {
+    QrCode::new(qr_friendly(data)).map_err(|e| format!("Unable to create QR code: {}", e))
+}
+
+fn to_gray_image(code: &QrCode, module_px: usize) -> GrayImage {
+    let bits = code.to_colors();
+    let width = code.width();
+    let size = (width + 2 * QUIET_ZONE) * module_px;
+    let mut imgbuf = GrayImage::from_pixel(size as u32, size as u32, Luma([255]));
+
+    for y in 0..width {
+        for x in 0..width {
+            if bits[y * width + x] != Color::Dark {
+                continue;
+            }
+            for dy in 0..module_px {
+                for dx in 0..module_px {
+                    imgbuf.put_pixel(
+                        ((x + QUIET_ZONE) * module_px + dx) as u32,
+                        ((y + QUIET_ZONE) * module_px + dy) as u32,
+                        Luma([0]),
+                    );
+                }
+            }
+        }
+    }
+    imgbuf
+}
+
+/// Saves `data` as a QR code PNG at `path`.
+pub fn save_png(data: &str, path: &Path) -> Result<(), String> {
+    let code = encode(data)?;
+    to_gray_image(&code, PNG_MODULE_PX)
+        .save(path)
+        .map_err(|e| format!("Unable to save QR code: {}", e))
+}
+
+/// A QR code shown on screen. Keeps its texture around and re-renders it
+/// only when the data or the display scale changes, so every module maps to
+/// a whole number of physical pixels and stays crisp.
+#[derive(Default)]
+pub struct QrView {
+    data: String,
+    texture: Option<TextureHandle>,
+    rendered_at: Option<(f32, f32)>,
+    error: Option<String>,
+}
+
+impl QrView {
+    pub fn set(&mut self, data: &str) {
+        if self.data != data {
+            self.data = data.to_string();
+            self.texture = None;
+            self.rendered_at = None;
+            self.error = None;
+        }
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+
+    pub fn data(&self) -> &str {
+        &self.data
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Draws the code at most `size` points wide, or the encoding error if any.
+    pub fn show(&mut self, ui: &mut egui::Ui, size: f32) {
+        if self.data.is_empty() {
+            return;
+        }
+
+        let pixels_per_point = ui.ctx().pixels_per_point();
+        if self.rendered_at != Some((pixels_per_point, size)) {
+            self.render(ui.ctx(), pixels_per_point, size);
+        }
+
+        if let Some(error) = &self.error {
+            ui.label(egui::RichText::new(error).color(egui::Color32::LIGHT_RED));
+        } else if let Some(texture) = &self.texture {
+            // Show at the texture's native size so no resampling blurs the modules.
+            let size = texture.size_vec2() / pixels_per_point;
+            ui.add(egui::Image::from_texture(texture).fit_to_exact_size(size));
+        }
+    }
+
+    fn render(&mut self, ctx: &egui::Context, pixels_per_point: f32, size: f32) {
+        self.rendered_at = Some((pixels_per_point, size));
+        match encode(&self.data) {
+            Ok(code) => {
+                let modules = code.width() + 2 * QUIET_ZONE;
+                let target_px = (size * pixels_per_point) as usize;
+                let module_px = (target_px / modules).max(1);
+                let imgbuf = to_gray_image(&code, module_px);
+
+                let side = imgbuf.width() as usize;
+                let color_image = ColorImage::from_gray([side, side], imgbuf.as_raw());
+                self.texture = Some(ctx.load_texture("qr_code", color_image, TextureOptions::NEAREST));
+                self.error = None;
+            }
+            Err(e) => {
+                self.texture = None;
+                self.error = Some(e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bip21_keeps_keys_and_plain_values() {
+        let uri = "bitcoin:tb1qexample?amount=0.001&label=Stable%20Channels&lightning=lntb1invoice&lno=lno1offer";
+        assert_eq!(
+            qr_friendly(uri),
+            "BITCOIN:TB1QEXAMPLE?amount=0.001&label=Stable%20Channels&lightning=LNTB1INVOICE&lno=LNO1OFFER"
+        );
+    }
+
+    #[test]
+    fn bip21_leaves_base58_addresses_alone() {
+        assert_eq!(
+            qr_friendly("bitcoin:1BoatSLRHtKNngkdXEeobR76b53LETtpyT?amount=1"),
+            "BITCOIN:1BoatSLRHtKNngkdXEeobR76b53LETtpyT?amount=1"
+        );
+        assert_eq!(qr_friendly("bitcoin:bc1qexample"), "BITCOIN:BC1QEXAMPLE");
+    }
+
+    #[test]
+    fn bech32_and_lightning_uris_are_uppercased() {
+        assert_eq!(qr_friendly("lnbc10u1invoice"), "LNBC10U1INVOICE");
+        assert_eq!(qr_friendly("lightning:lnbc10u1invoice"), "LIGHTNING:LNBC10U1INVOICE");
+    }
+
+    #[test]
+    fn other_data_is_left_alone() {
+        assert_eq!(qr_friendly("https://example.com/Pay"), "https://example.com/Pay");
+    }
+}
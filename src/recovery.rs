@@ -0,0 +1,66 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use ldk_node::bitcoin::secp256k1::PublicKey;
+use ldk_node::{ChannelDetails, Node};
+
+/// Blocks LDK uses for `to_self_delay` when the channel doesn't say.
+const DEFAULT_SPEND_DELAY_BLOCKS: u16 = 144;
+
+pub fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+/// Whether we currently have a connection to `counterparty`.
+pub fn is_connected(node: &Node, counterparty: &PublicKey) -> bool {
+    node.list_peers()
+        .iter()
+        .any(|peer| peer.node_id == *counterparty && peer.is_connected)
+}
+
+/// Seconds since the counterparty was last seen, or `None` if it is
+/// connected right now or we have never seen it.
+pub fn offline_secs(connected: bool, last_seen: Option<i64>, now: i64) -> Option<u64> {
+    if connected {
+        return None;
+    }
+    last_seen.map(|seen| now.saturating_sub(seen).max(0) as u64)
+}
+
+pub fn format_duration(secs: u64) -> String {
+    match secs {
+        s if s < 3600 => format!("{} min", s / 60),
+        s if s < 2 * 86_400 => format!("{} hours", s / 3600),
+        s => format!("{} days", s / 86_400),
+    }
+}
+
+/// Blocks our balance stays locked after we force-close `channel`.
+pub fn spend_delay_blocks(channel: &ChannelDetails) -> u16 {
+    channel.force_close_spend_delay.unwrap_or(DEFAULT_SPEND_DELAY_BLOCKS)
+}
+
+/// What happens to the user's funds if they force-close `channel`.
+pub fn force_close_explanation(channel: &ChannelDetails) -> String {
+    let blocks = spend_delay_blocks(channel);
+    format!(
+        "Force-closing publishes your latest channel state without your channel partner. \
+         Your balance is then time-locked for {} blocks (about {}) before it can be swept, \
+         and the on-chain fee was fixed when that state was signed, so it may take a while \
+         to confirm. Your funds stop being stabilized as soon as you force-close.",
+        blocks,
+        format_duration(blocks as u64 * 600)
+    )
+}
+
+/// Unilaterally closes `channel` by broadcasting our latest commitment.
+pub fn force_close_channel(node: &Node, channel: &ChannelDetails, reason: &str) -> Result<(), String> {
+    node.force_close_channel(
+        &channel.user_channel_id,
+        channel.counterparty_node_id,
+        Some(reason.to_string()),
+    )
+    .map_err(|e| format!("Failed to force-close channel: {}", e))
+}
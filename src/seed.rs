@@ -0,0 +1,240 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use eframe::egui;
+use ldk_node::bip39::Mnemonic;
+use ldk_node::bitcoin::hashes::{sha256, Hash};
+
+use crate::crypto::{self, data_key};
+
+/// Recovery phrase of wallets created by this app.
+const MNEMONIC_FILE: &str = "keys_mnemonic";
+
+/// `MNEMONIC_FILE` once encrypted with the passphrase.
+const ENCRYPTED_MNEMONIC_FILE: &str = "keys_mnemonic.enc";
+
+/// Raw seed LDK generates when no entropy is configured. Wallets created
+/// before recovery phrases were added only have this.
+const SEED_FILE: &str = "keys_seed";
+
+/// `SEED_FILE` once encrypted with the passphrase. LDK can't read it, so we
+/// decrypt it and hand the bytes to the builder.
+const ENCRYPTED_SEED_FILE: &str = "keys_seed.enc";
+
+/// Each seed file and its encrypted counterpart.
+const SEED_FILE_PAIRS: [(&str, &str); 2] = [
+    (MNEMONIC_FILE, ENCRYPTED_MNEMONIC_FILE),
+    (SEED_FILE, ENCRYPTED_SEED_FILE),
+];
+
+/// How many words the backup quiz asks for.
+const QUIZ_WORDS: usize = 3;
+
+/// What the node's keys are derived from.
+pub enum WalletSeed {
+    Mnemonic(Mnemonic),
+    /// Decrypted contents of `ENCRYPTED_SEED_FILE`.
+    SeedBytes(Vec<u8>),
+    SeedFile(PathBuf),
+    None,
+}
+
+/// Fails if a seed file exists but can't be read or decrypted, so a damaged
+/// wallet is never mistaken for no wallet and set up afresh.
+pub fn find_seed(node_dir: &Path) -> Result<WalletSeed, String> {
+    if let Some(mnemonic) = load_mnemonic(node_dir)? {
+        return Ok(WalletSeed::Mnemonic(mnemonic));
+    }
+    if let Some(seed) = read_sealed(&node_dir.join(ENCRYPTED_SEED_FILE))? {
+        return Ok(WalletSeed::SeedBytes(seed));
+    }
+    let seed_file = node_dir.join(SEED_FILE);
+    if seed_file.exists() {
+        Ok(WalletSeed::SeedFile(seed_file))
+    } else {
+        Ok(WalletSeed::None)
+    }
+}
+
+/// Decrypts an encrypted seed file, if there is one.
+fn read_sealed(path: &Path) -> Result<Option<Vec<u8>>, String> {
+    let sealed = match fs::read(path) {
+        Ok(sealed) => sealed,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(format!("Failed to read {}: {}", path.display(), e)),
+    };
+    let key = data_key().ok_or_else(|| format!("{} is encrypted but no passphrase was entered", path.display()))?;
+    crypto::open(&key, &sealed)
+        .map(Some)
+        .map_err(|e| format!("Failed to decrypt {}: {}", path.display(), e))
+}
+
+/// Writes a file only the user can read, through a temp file so it is never
+/// left half written.
+fn write_private(path: &Path, data: &[u8]) -> io::Result<()> {
+    let tmp_path = path.with_extension("tmp");
+    fs::write(&tmp_path, data)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&tmp_path, fs::Permissions::from_mode(0o600))?;
+    }
+    fs::rename(&tmp_path, path)
+}
+
+pub fn generate_mnemonic() -> Mnemonic {
+    ldk_node::generate_entropy_mnemonic()
+}
+
+/// Parses a recovery phrase typed by the user, ignoring case and spacing.
+pub fn parse_mnemonic(input: &str) -> Result<Mnemonic, String> {
+    let words: Vec<String> = input.split_whitespace().map(|w| w.to_lowercase()).collect();
+    if !matches!(words.len(), 12 | 24) {
+        return Err("A recovery phrase has 12 or 24 words".to_string());
+    }
+    Mnemonic::parse(words.join(" ")).map_err(|e| format!("Invalid recovery phrase: {}", e))
+}
+
+pub fn save_mnemonic(node_dir: &Path, mnemonic: &Mnemonic) -> io::Result<()> {
+    fs::create_dir_all(node_dir)?;
+    let phrase = mnemonic.to_string().into_bytes();
+    match data_key() {
+        Some(key) => {
+            let sealed = crypto::seal(&key, &phrase).map_err(io::Error::other)?;
+            write_private(&node_dir.join(ENCRYPTED_MNEMONIC_FILE), &sealed)
+        }
+        None => write_private(&node_dir.join(MNEMONIC_FILE), &phrase),
+    }
+}
+
+pub fn load_mnemonic(node_dir: &Path) -> Result<Option<Mnemonic>, String> {
+    let data = match read_sealed(&node_dir.join(ENCRYPTED_MNEMONIC_FILE))? {
+        Some(data) => data,
+        None => match fs::read(node_dir.join(MNEMONIC_FILE)) {
+            Ok(data) => data,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(format!("Failed to read recovery phrase: {}", e)),
+        },
+    };
+    let phrase = String::from_utf8(data).map_err(|_| "Recovery phrase on disk is corrupted".to_string())?;
+    Mnemonic::parse(phrase.trim())
+        .map(Some)
+        .map_err(|e| format!("Invalid recovery phrase on disk: {}", e))
+}
+
+/// The file holding the wallet's raw seed, encrypted or not.
+pub fn seed_file_path(node_dir: &Path) -> PathBuf {
+    let encrypted = node_dir.join(ENCRYPTED_SEED_FILE);
+    if encrypted.exists() {
+        encrypted
+    } else {
+        node_dir.join(SEED_FILE)
+    }
+}
+
+/// Writes an encrypted copy of each plaintext seed file with the data key.
+/// The plaintext files stay until `remove_plaintext_seed_files`.
+pub fn encrypt_seed_files(node_dir: &Path) -> io::Result<()> {
+    let key = data_key().ok_or_else(|| io::Error::other("No passphrase set"))?;
+    for (plain, encrypted) in SEED_FILE_PAIRS {
+        let data = match fs::read(node_dir.join(plain)) {
+            Ok(data) => data,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+        let sealed = crypto::seal(&key, &data).map_err(io::Error::other)?;
+        write_private(&node_dir.join(encrypted), &sealed)?;
+    }
+    Ok(())
+}
+
+/// Whether a seed file is still waiting to be encrypted.
+pub fn plaintext_seed_files_exist(node_dir: &Path) -> bool {
+    SEED_FILE_PAIRS.iter().any(|(plain, _)| node_dir.join(plain).exists())
+}
+
+/// Deletes the plaintext seed files, each only once its encrypted copy
+/// reads back the same. LDK would otherwise keep using `keys_seed`.
+pub fn remove_plaintext_seed_files(node_dir: &Path) -> io::Result<()> {
+    for (plain, encrypted) in SEED_FILE_PAIRS {
+        let data = match fs::read(node_dir.join(plain)) {
+            Ok(data) => data,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+        if read_sealed(&node_dir.join(encrypted)).map_err(io::Error::other)? != Some(data) {
+            return Err(io::Error::other(format!("Encrypted copy of {} doesn't match", plain)));
+        }
+        fs::remove_file(node_dir.join(plain))?;
+    }
+    Ok(())
+}
+
+/// Shows the words of `mnemonic` numbered, in two columns.
+pub fn show_words(ui: &mut egui::Ui, mnemonic: &Mnemonic) {
+    let words: Vec<&str> = mnemonic.words().collect();
+    let rows = words.len().div_ceil(2);
+    egui::Grid::new("mnemonic_words").spacing([40.0, 6.0]).show(ui, |ui| {
+        for row in 0..rows {
+            for i in [row, row + rows] {
+                if let Some(word) = words.get(i) {
+                    ui.label(egui::RichText::new(format!("{:>2}. {}", i + 1, word)).monospace().size(16.0));
+                }
+            }
+            ui.end_row();
+        }
+    });
+}
+
+/// Asks for a few words of the recovery phrase to check it was written down.
+pub struct BackupQuiz {
+    positions: Vec<usize>,
+    answers: Vec<String>,
+}
+
+impl BackupQuiz {
+    /// Picks `QUIZ_WORDS` distinct positions out of `word_count`.
+    pub fn new(word_count: usize) -> Self {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos())
+            .unwrap_or(0);
+        let mut hash = sha256::Hash::hash(&nanos.to_le_bytes());
+        let mut positions = Vec::new();
+        while positions.len() < QUIZ_WORDS.min(word_count) {
+            for byte in hash.to_byte_array() {
+                let position = byte as usize % word_count;
+                if positions.len() < QUIZ_WORDS && !positions.contains(&position) {
+                    positions.push(position);
+                }
+            }
+            hash = sha256::Hash::hash(hash.as_byte_array());
+        }
+        positions.sort_unstable();
+
+        Self {
+            answers: vec![String::new(); positions.len()],
+            positions,
+        }
+    }
+
+    pub fn show(&mut self, ui: &mut egui::Ui) {
+        egui::Grid::new("backup_quiz").spacing([10.0, 6.0]).show(ui, |ui| {
+            for (position, answer) in self.positions.iter().zip(self.answers.iter_mut()) {
+                ui.label(format!("Word #{}", position + 1));
+                ui.add(egui::TextEdit::singleline(answer).desired_width(140.0));
+                ui.end_row();
+            }
+        });
+    }
+
+    pub fn passed(&self, mnemonic: &Mnemonic) -> bool {
+        let words: Vec<&str> = mnemonic.words().collect();
+        self.positions
+            .iter()
+            .zip(&self.answers)
+            .all(|(position, answer)| words.get(*position) == Some(&answer.trim().to_lowercase().as_str()))
+    }
+}
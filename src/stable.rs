@@ -2,17 +2,35 @@ use core::f64;
 use std::str::FromStr;
 
 // use ldk_node::bitcoin::secp256k1::PublicKey;
-use ldk_node::bitcoin::{Address, Network};
+use ldk_node::bitcoin::{Address, Network, Txid};
+use ldk_node::lightning::ln::channelmanager::PaymentId;
+use ldk_node::lightning::offers::offer::Offer;
+use ldk_node::payment::PaymentKind;
 use ldk_node::{Node, ChannelDetails};
+use serde::{Deserialize, Serialize};
 // use lightning::ln::msgs::SocketAddress;
 // use lightning::routing::gossip::NodeId;
-use ureq::Agent;
-use crate::types::{Bitcoin, StableChannel, USD};
-use crate::price_feeds::{calculate_median_price, fetch_prices, set_price_feeds};
+use crate::types::{Bitcoin, PegTarget, StableChannel, USD};
+use crate::price_feeds::price_service;
+use crate::price_sources::Attestation;
+use crate::recovery::unix_now;
+use crate::pricing::{record_sample, PricingMode};
+
+/// What a stability check did.
+pub enum StabilityOutcome {
+    /// Close enough to the peg, nothing to do.
+    Stable,
+    /// Below the peg, waiting for the counterparty to pay.
+    Waiting,
+    /// Paid the counterparty the difference.
+    Paid(StabilizationRecord),
+    PaymentFailed(String),
+    HighRisk(i32),
+}
 
 /// Core stability logic
-pub fn check_stability(node: &Node, sc: &mut StableChannel) {
-    sc.latest_price = get_latest_price();
+pub fn check_stability(node: &Node, sc: &mut StableChannel) -> StabilityOutcome {
+    refresh_price(sc);
 
     if let Some(channel) = node
         .list_channels()
@@ -22,10 +40,13 @@ pub fn check_stability(node: &Node, sc: &mut StableChannel) {
         update_balances(sc, Some(channel.clone()));
     }
 
-    let mut dollars_from_par: USD = sc.stable_receiver_usd - sc.expected_usd;
+    // Only the pegged portion is corrected; native bitcoin floats with the price.
+    let mut target_usd = stable_receiver_target(sc);
+    let mut dollars_from_par: USD = sc.stable_receiver_usd - target_usd;
     let mut percent_from_par = ((dollars_from_par / sc.expected_usd) * 100.0).abs();
 
     println!("{:<25} {:>15}", "Expected USD:", sc.expected_usd);
+    println!("{:<25} {:>15}", "Native BTC:", sc.native_btc);
     println!("{:<25} {:>15}", "User USD:", sc.stable_receiver_usd);
     println!("{:<25} {:>5}", "Percent from par:", format!("{:.2}%\n", percent_from_par));
 
@@ -39,10 +60,10 @@ pub fn check_stability(node: &Node, sc: &mut StableChannel) {
         HighRisk,
     }
 
-    let action = if percent_from_par < 0.1 {
+    let action = if sc.expected_usd.0 <= 0.0 || percent_from_par < 0.1 {
         Action::DoNothing
     } else {
-        let is_receiver_below_expected: bool = sc.stable_receiver_usd < sc.expected_usd;
+        let is_receiver_below_expected: bool = sc.stable_receiver_usd < target_usd;
 
         match (sc.is_stable_receiver, is_receiver_below_expected, sc.risk_level > 100) {
             (_, _, true) => Action::HighRisk, // High risk scenario
@@ -54,7 +75,10 @@ pub fn check_stability(node: &Node, sc: &mut StableChannel) {
     };
 
     match action {
-        Action::DoNothing => println!("\nDifference from par less than 0.1%. Doing nothing."),
+        Action::DoNothing => {
+            println!("\nDifference from par less than 0.1%. Doing nothing.");
+            StabilityOutcome::Stable
+        }
         Action::Wait => {
             // println!("\nWaiting 10 seconds and checking on payment...\n");
             // std::thread::sleep(std::time::Duration::from_secs(10));
@@ -70,7 +94,8 @@ pub fn check_stability(node: &Node, sc: &mut StableChannel) {
             println!("{:<25} {:>15}", "Expected USD:", sc.expected_usd);
             println!("{:<25} {:>15}", "User USD:", sc.stable_receiver_usd);
 
-            dollars_from_par = sc.stable_receiver_usd - sc.expected_usd;
+            target_usd = stable_receiver_target(sc);
+            dollars_from_par = sc.stable_receiver_usd - target_usd;
             percent_from_par = ((dollars_from_par / sc.expected_usd) * 100.0).abs();
 
             println!(
@@ -80,6 +105,7 @@ pub fn check_stability(node: &Node, sc: &mut StableChannel) {
             );
 
             println!("{:<25} {:>15}", "LSP USD:", sc.stable_provider_usd);
+            StabilityOutcome::Waiting
         }
         Action::Pay => {
             println!("\nPaying the difference...\n");
@@ -104,32 +130,301 @@ pub fn check_stability(node: &Node, sc: &mut StableChannel) {
             //     println!("Successfully connected.");
             // }
 
-            let result = node
-                .spontaneous_payment()
-                .send(amt, sc.counterparty,None);
+            let result = node.spontaneous_payment().send(amt, sc.counterparty, None);
             match result {
-                Ok(payment_id) => println!("Payment sent successfully with payment ID: {}", payment_id),
-                Err(e) => println!("Failed to send payment: {}", e),
+                Ok(payment_id) => {
+                    println!("Payment sent successfully with payment ID: {}", payment_id);
+                    StabilityOutcome::Paid(stabilization_record(
+                        sc,
+                        payment_id.to_string(),
+                        true,
+                        // The provider pays when below par, so the difference is negative there.
+                        USD::from_f64(dollars_from_par.0.abs()),
+                    ))
+                }
+                Err(e) => {
+                    println!("Failed to send payment: {}", e);
+                    StabilityOutcome::PaymentFailed(e.to_string())
+                }
             }
 
         }
         Action::HighRisk => {
             println!("Risk level high. Current risk level: {}", sc.risk_level);
+            StabilityOutcome::HighRisk(sc.risk_level)
         }
     }
 }
 
 pub fn get_latest_price() -> f64 {
-    let latest_price = fetch_prices(&Agent::new(), &set_price_feeds())
-        .and_then(|prices| calculate_median_price(prices))
-        .unwrap_or(0.0);
-    latest_price
+    price_service().latest().median.unwrap_or(0.0)
+}
+
+/// Stores the latest quotes and their median on `sc`, and sets the price
+/// stabilization uses according to `sc.pricing_mode`. Both prices are 0.0
+/// when no feed answered.
+pub fn refresh_price(sc: &mut StableChannel) {
+    let snapshot = price_service().latest();
+    sc.prices = snapshot.quotes;
+    sc.prices_updated = Some(snapshot.fetched_at);
+    sc.spot_price = snapshot.median.unwrap_or(0.0);
+    if sc.spot_price <= 0.0 {
+        sc.latest_price = 0.0;
+        return;
+    }
+
+    record_sample(&mut sc.price_samples, sc.pricing_mode, snapshot.fetched_at, sc.spot_price);
+    sc.latest_price = sc
+        .pricing_mode
+        .price(&sc.price_samples, snapshot.fetched_at)
+        .unwrap_or(sc.spot_price);
+}
+
+/// What the stable receiver's balance should be worth right now: the pegged
+/// dollars plus the native bitcoin at the latest price.
+pub fn stable_receiver_target(sc: &StableChannel) -> USD {
+    sc.expected_usd + USD::from_bitcoin(sc.native_btc, sc.latest_price)
+}
+
+/// Splits the stable receiver's balance into a pegged and a native bitcoin
+/// portion according to `sc.peg_target`. Expects balances to be up to date.
+pub fn apply_peg_target(sc: &mut StableChannel) {
+    if sc.latest_price <= 0.0 {
+        return;
+    }
+
+    sc.expected_usd = match sc.peg_target {
+        PegTarget::FixedUsd(usd) => usd,
+        PegTarget::Percent(percent) => {
+            USD::from_f64(sc.stable_receiver_usd.0 * percent.clamp(0.0, 100.0) / 100.0)
+        }
+    };
+
+    let pegged_btc = Bitcoin::from_usd(sc.expected_usd, sc.latest_price);
+    sc.native_btc = sc.stable_receiver_btc - pegged_btc;
+    sc.expected_btc = pegged_btc;
+}
+
+/// Moves `amount` of the pegged balance into native bitcoin at the latest price.
+/// No funds move in the channel; only the split changes.
+pub fn move_to_native(sc: &mut StableChannel, amount: USD) -> Result<(), String> {
+    if amount.0 <= 0.0 {
+        return Err("Amount must be greater than zero".to_string());
+    }
+    if amount > sc.expected_usd {
+        return Err(format!("Only {} is stable", sc.expected_usd));
+    }
+    if sc.latest_price <= 0.0 {
+        return Err("No price available".to_string());
+    }
+
+    sc.expected_usd = sc.expected_usd - amount;
+    sc.native_btc = sc.native_btc + Bitcoin::from_usd(amount, sc.latest_price);
+    sc.expected_btc = Bitcoin::from_usd(sc.expected_usd, sc.latest_price);
+    sc.peg_target = PegTarget::FixedUsd(sc.expected_usd);
+    Ok(())
+}
+
+/// Moves `amount` worth of native bitcoin into the pegged balance at the latest price.
+pub fn move_to_stable(sc: &mut StableChannel, amount: USD) -> Result<(), String> {
+    if amount.0 <= 0.0 {
+        return Err("Amount must be greater than zero".to_string());
+    }
+    if sc.latest_price <= 0.0 {
+        return Err("No price available".to_string());
+    }
+    let btc = Bitcoin::from_usd(amount, sc.latest_price);
+    if btc > sc.native_btc {
+        return Err(format!("Only {} is held as native bitcoin", sc.native_btc));
+    }
+
+    sc.native_btc = sc.native_btc - btc;
+    sc.expected_usd = sc.expected_usd + amount;
+    sc.expected_btc = Bitcoin::from_usd(sc.expected_usd, sc.latest_price);
+    sc.peg_target = PegTarget::FixedUsd(sc.expected_usd);
+    Ok(())
+}
+
+/// Adds funds that entered the channel to the pegged balance.
+pub fn increase_stable_balance(sc: &mut StableChannel, amount: USD) {
+    sc.expected_usd = sc.expected_usd + amount;
+    sc.expected_btc = Bitcoin::from_usd(sc.expected_usd, sc.latest_price);
+    sc.peg_target = PegTarget::FixedUsd(sc.expected_usd);
+}
+
+/// Takes funds that left the channel out of the pegged balance. Anything beyond
+/// the pegged balance is taken from native bitcoin.
+pub fn decrease_stable_balance(sc: &mut StableChannel, amount: USD) {
+    if amount <= sc.expected_usd {
+        sc.expected_usd = sc.expected_usd - amount;
+    } else {
+        let remainder = amount - sc.expected_usd;
+        sc.expected_usd = USD::from_f64(0.0);
+        sc.native_btc = sc.native_btc - Bitcoin::from_usd(remainder, sc.latest_price);
+    }
+    sc.expected_btc = Bitcoin::from_usd(sc.expected_usd, sc.latest_price);
+    sc.peg_target = PegTarget::FixedUsd(sc.expected_usd);
+}
+
+/// A stabilization payment and the price it was made at.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StabilizationRecord {
+    pub payment_id: String,
+    pub timestamp: i64,
+    /// Whether we paid the counterparty or they paid us.
+    pub outgoing: bool,
+    pub amount_usd: USD,
+    /// The price settled at, per `pricing_mode`.
+    pub price: f64,
+    /// Median of the quotes at the time.
+    #[serde(default)]
+    pub spot_price: f64,
+    #[serde(default)]
+    pub pricing_mode: PricingMode,
+    /// Signed oracle prices among the quotes `spot_price` was taken from.
+    /// With TWAP or EMA pricing `price` is an average of earlier medians,
+    /// which these don't attest to.
+    pub attestations: Vec<Attestation>,
+}
+
+/// Oracle attestations of the quotes that went into the current price.
+fn price_attestations(sc: &StableChannel) -> Vec<Attestation> {
+    sc.prices
+        .iter()
+        .filter(|quote| !quote.excluded)
+        .filter_map(|quote| quote.attestation.clone())
+        .collect()
+}
+
+pub fn stabilization_record(
+    sc: &StableChannel,
+    payment_id: String,
+    outgoing: bool,
+    amount_usd: USD,
+) -> StabilizationRecord {
+    StabilizationRecord {
+        payment_id,
+        timestamp: unix_now(),
+        outgoing,
+        amount_usd,
+        price: sc.latest_price,
+        spot_price: sc.spot_price,
+        pricing_mode: sc.pricing_mode,
+        attestations: price_attestations(sc),
+    }
+}
+
+/// Prefix of the payer note carrying a [`StableAgreement`].
+const AGREEMENT_NOTE_PREFIX: &str = "stable-agreement:";
+
+/// Paid with an agreement update. Bolt12 payments need an amount.
+pub const AGREEMENT_UPDATE_MSATS: u64 = 1_000;
+
+/// What both sides of the channel stabilize against. The stable receiver
+/// sends it whenever it changes, as the payer note of a small Bolt12
+/// payment to the counterparty's offer; ldk-node keeps the note with the
+/// received payment, so the counterparty can read it back.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StableAgreement {
+    pub channel_id: String,
+    pub expected_usd: USD,
+    pub native_btc: Bitcoin,
+    #[serde(default)]
+    pub pricing_mode: PricingMode,
+}
+
+impl StableAgreement {
+    pub fn new(sc: &StableChannel) -> Self {
+        Self {
+            channel_id: hex::encode(sc.channel_id.0),
+            expected_usd: sc.expected_usd,
+            native_btc: sc.native_btc,
+            pricing_mode: sc.pricing_mode,
+        }
+    }
+
+    fn to_note(&self) -> Result<String, String> {
+        serde_json::to_string(self)
+            .map(|json| format!("{}{}", AGREEMENT_NOTE_PREFIX, json))
+            .map_err(|e| format!("Failed to encode the agreement: {}", e))
+    }
+
+    fn from_note(note: &str) -> Option<Self> {
+        serde_json::from_str(note.strip_prefix(AGREEMENT_NOTE_PREFIX)?).ok()
+    }
+
+    /// Takes the agreement over, if it is for our channel.
+    pub fn apply_to(&self, sc: &mut StableChannel) -> bool {
+        if self.channel_id != hex::encode(sc.channel_id.0) {
+            return false;
+        }
+        sc.expected_usd = self.expected_usd;
+        sc.native_btc = self.native_btc;
+        sc.pricing_mode = self.pricing_mode;
+        sc.expected_btc = Bitcoin::from_usd(sc.expected_usd, sc.latest_price);
+        sc.peg_target = PegTarget::FixedUsd(sc.expected_usd);
+        true
+    }
+}
+
+/// An agreement on its way to the counterparty.
+pub struct AgreementUpdate {
+    pub payment_id: PaymentId,
+    pub agreement: StableAgreement,
+    /// Taken out of the native balance to pay for the update.
+    fee: Bitcoin,
+}
+
+impl AgreementUpdate {
+    /// Gives the fee back once the update payment has failed.
+    pub fn failed(&self, sc: &mut StableChannel) {
+        sc.native_btc = sc.native_btc + self.fee;
+    }
+}
+
+/// Sends the current agreement to the counterparty. The update payment
+/// comes out of the native balance, so the agreement sent already accounts
+/// for it.
+pub fn send_agreement(node: &Node, sc: &mut StableChannel, offer: &Offer) -> Result<AgreementUpdate, String> {
+    let fee = Bitcoin::from_sats((AGREEMENT_UPDATE_MSATS / 1000).min(sc.native_btc.sats));
+    sc.native_btc = sc.native_btc - fee;
+    let agreement = StableAgreement::new(sc);
+    let result = agreement.to_note().and_then(|note| {
+        node.bolt12_payment()
+            .send_using_amount(offer, AGREEMENT_UPDATE_MSATS, None, Some(note))
+            .map_err(|e| format!("Failed to send the agreement: {}", e))
+    });
+    match result {
+        Ok(payment_id) => Ok(AgreementUpdate { payment_id, agreement, fee }),
+        Err(e) => {
+            sc.native_btc = sc.native_btc + fee;
+            Err(e)
+        }
+    }
+}
+
+/// The agreement sent with a received payment, if it carried one.
+pub fn received_agreement(node: &Node, payment_hash: [u8; 32]) -> Option<StableAgreement> {
+    match node.payment(&PaymentId(payment_hash))?.kind {
+        PaymentKind::Bolt12Offer { payer_note: Some(note), .. } => StableAgreement::from_note(&note.0),
+        _ => None,
+    }
+}
+
+/// Stabilization payments are keysends; everything the user receives comes
+/// with an invoice or offer. Other incoming payments are native bitcoin.
+pub fn is_stabilization_payment(node: &Node, payment_hash: [u8; 32]) -> bool {
+    node.payment(&PaymentId(payment_hash))
+        .is_some_and(|payment| matches!(payment.kind, PaymentKind::Spontaneous { .. }))
 }
 
 pub fn update_balances(sc: &mut StableChannel, channel_details: Option<ChannelDetails>) {
 
     let (our_balance, their_balance) = match channel_details {
         Some(channel) => {
+            if channel.funding_txo.is_some() {
+                sc.funding_txo = channel.funding_txo;
+            }
             let unspendable_punishment_sats = channel.unspendable_punishment_reserve.unwrap_or(0);
             let our_balance_sats =
                 (channel.outbound_capacity_msat / 1000) + unspendable_punishment_sats;
@@ -210,25 +505,45 @@ pub fn update_balances(sc: &mut StableChannel, channel_details: Option<ChannelDe
 //     (channels, info)
 // }
 
-pub fn close_channels_to_address(node: &Node, address_str: String) {
-    for channel in node.list_channels().iter() {
-        let user_channel_id = channel.user_channel_id;
-        let counterparty_node_id = channel.counterparty_node_id;
-        let _ = node.close_channel(&user_channel_id, counterparty_node_id);
-    }
+/// Rough weight of a cooperative closing transaction: the 2-of-2 funding
+/// input plus one output per side.
+const CLOSING_TX_WEIGHT: u64 = 724;
+
+/// Rough weight of sweeping a single P2WPKH output to one address.
+const SWEEP_TX_WEIGHT: u64 = 440;
+
+/// Fees to expect when closing `channel` and sweeping the funds, in sats, at
+/// the channel's current fee rate.
+pub fn estimate_close_fees(channel: &ChannelDetails) -> (u64, u64) {
+    let feerate_per_kw = channel.feerate_sat_per_1000_weight as u64;
+    (
+        feerate_per_kw * CLOSING_TX_WEIGHT / 1000,
+        feerate_per_kw * SWEEP_TX_WEIGHT / 1000,
+    )
+}
 
-    // Withdraw everything to address
-    match Address::from_str(&address_str) {
+/// Parses a withdrawal address and checks it is for the network the node runs on.
+pub fn validate_withdrawal_address(address_str: &str, network: Network) -> Result<Address, String> {
+    Address::from_str(address_str.trim())
+        .map_err(|_| "Invalid address".to_string())?
+        .require_network(network)
+        .map_err(|_| format!("Address is not for {}", network))
+}
 
-        Ok(addr) => match addr.require_network(Network::Signet,) {
-            Ok(addr_checked) => {
-                match node.onchain_payment().send_all_to_address(&addr_checked) {
-                    Ok(txid) => println!("{}", txid),
-                    Err(e) => eprintln!("Error: {}", e),
-                }
-            }
-            Err(_) => eprintln!("Invalid address for this network"),
-        },
-        Err(_) => eprintln!("Invalid address"),
-    }
-}
\ No newline at end of file
+/// Cooperatively closes a single channel. Funds land in the on-chain wallet
+/// and are swept to the withdrawal address once spendable.
+pub fn close_channel(node: &Node, channel: &ChannelDetails) -> Result<(), String> {
+    node.close_channel(&channel.user_channel_id, channel.counterparty_node_id)
+        .map_err(|e| format!("Failed to close channel: {}", e))
+}
+
+/// Sends `amount_sats` to `address`, or the whole on-chain balance if
+/// `None`. Only call this once the closed channel's funds are spendable, or
+/// they'll be left behind.
+pub fn sweep_to_address(node: &Node, address: &Address, amount_sats: Option<u64>) -> Result<Txid, String> {
+    let result = match amount_sats {
+        Some(amount_sats) => node.onchain_payment().send_to_address(address, amount_sats),
+        None => node.onchain_payment().send_all_to_address(address),
+    };
+    result.map_err(|e| format!("Failed to sweep funds: {}", e))
+}
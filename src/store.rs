@@ -0,0 +1,230 @@
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+use ldk_node::bitcoin::OutPoint;
+use ldk_node::lightning::ln::types::ChannelId;
+use serde::{Deserialize, Serialize};
+
+use crate::crypto::{open_at_rest, seal_at_rest};
+use crate::lifecycle::ChannelLifecycle;
+use crate::pricing::PricingMode;
+use crate::types::{Bitcoin, PegTarget, StableChannel, USD};
+use crate::history::PriceHistory;
+use crate::stable::StabilizationRecord;
+use crate::wallet::OnchainLog;
+
+const STABLE_CHANNEL_FILE: &str = "stable_channel.json";
+const LIFECYCLE_FILE: &str = "lifecycle.json";
+const ONCHAIN_LOG_FILE: &str = "onchain_log.json";
+const BACKUP_FILE: &str = "backup.json";
+const CHANNEL_BACKUP_FILE: &str = "channel_backup.json";
+const PRICE_HISTORY_FILE: &str = "price_history.json";
+const STABILIZATIONS_FILE: &str = "stabilizations.json";
+
+/// The parts of a `StableChannel` that make up the agreement with the
+/// counterparty and must survive a restart. Balances and prices are
+/// recomputed from the node on every stability check.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StableChannelState {
+    pub channel_id: String,
+    pub peg_target: PegTarget,
+    #[serde(default)]
+    pub pricing_mode: PricingMode,
+    pub expected_usd: USD,
+    pub native_btc: Bitcoin,
+    #[serde(default)]
+    pub funding_txo: Option<String>,
+    #[serde(default)]
+    pub sweep_txid: Option<String>,
+    #[serde(default)]
+    pub withdrawal_address: Option<String>,
+    #[serde(default)]
+    pub onchain_before_close_sats: Option<u64>,
+    /// (payment hash hex, amount)
+    #[serde(default)]
+    pub pending_stable_receives: Vec<(String, USD)>,
+    /// Channels saved before agreements were sent haven't sent theirs.
+    #[serde(default = "agreement_pending_default")]
+    pub agreement_pending: bool,
+}
+
+fn agreement_pending_default() -> bool {
+    true
+}
+
+impl StableChannelState {
+    pub fn from_stable_channel(sc: &StableChannel) -> Self {
+        Self {
+            channel_id: hex::encode(sc.channel_id.0),
+            peg_target: sc.peg_target,
+            pricing_mode: sc.pricing_mode,
+            expected_usd: sc.expected_usd,
+            native_btc: sc.native_btc,
+            funding_txo: sc.funding_txo.map(|txo| txo.to_string()),
+            sweep_txid: sc.sweep_txid.clone(),
+            withdrawal_address: sc.withdrawal_address.clone(),
+            onchain_before_close_sats: sc.onchain_before_close_sats,
+            pending_stable_receives: sc
+                .pending_stable_receives
+                .iter()
+                .map(|(hash, amount)| (hex::encode(hash), *amount))
+                .collect(),
+            agreement_pending: sc.agreement_pending,
+        }
+    }
+
+    pub fn channel_id(&self) -> Option<ChannelId> {
+        let bytes: [u8; 32] = hex::decode(&self.channel_id).ok()?.try_into().ok()?;
+        Some(ChannelId::from_bytes(bytes))
+    }
+
+    pub fn apply_to(&self, sc: &mut StableChannel) {
+        sc.peg_target = self.peg_target;
+        sc.pricing_mode = self.pricing_mode;
+        sc.expected_usd = self.expected_usd;
+        sc.native_btc = self.native_btc;
+        sc.funding_txo = self
+            .funding_txo
+            .as_deref()
+            .and_then(|txo| OutPoint::from_str(txo).ok());
+        sc.sweep_txid = self.sweep_txid.clone();
+        sc.withdrawal_address = self.withdrawal_address.clone();
+        sc.onchain_before_close_sats = self.onchain_before_close_sats;
+        sc.pending_stable_receives = self
+            .pending_stable_receives
+            .iter()
+            .filter_map(|(hash, amount)| {
+                let hash: [u8; 32] = hex::decode(hash).ok()?.try_into().ok()?;
+                Some((hash, *amount))
+            })
+            .collect();
+        sc.agreement_pending = self.agreement_pending;
+    }
+}
+
+/// Where channel backups go and when the last one was written.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ChannelBackupSettings {
+    pub dir: Option<String>,
+    pub last_export: Option<i64>,
+}
+
+/// Every file the app keeps in the state directory.
+const STATE_FILES: &[&str] = &[
+    STABLE_CHANNEL_FILE,
+    LIFECYCLE_FILE,
+    ONCHAIN_LOG_FILE,
+    BACKUP_FILE,
+    CHANNEL_BACKUP_FILE,
+    PRICE_HISTORY_FILE,
+    STABILIZATIONS_FILE,
+];
+
+/// Writes `value` as JSON to `dir/file`, encrypted if a passphrase is set.
+/// Goes through a temp file so a crash never leaves a truncated file behind.
+fn write_json<T: Serialize>(dir: &Path, file: &str, value: &T) -> Result<(), Box<dyn Error>> {
+    fs::create_dir_all(dir)?;
+    let json = serde_json::to_vec_pretty(value)?;
+    let data = seal_at_rest(json)?;
+    let tmp_path = dir.join(format!("{}.tmp", file));
+    fs::write(&tmp_path, data)?;
+    fs::rename(&tmp_path, dir.join(file))?;
+    Ok(())
+}
+
+/// Decrypts the contents of a state file. Plaintext JSON is accepted as
+/// well: state files are only resealed once encryption has been turned on.
+pub fn open_state_file(data: Vec<u8>) -> Result<Vec<u8>, String> {
+    match open_at_rest(data.clone()) {
+        Ok(content) => Ok(content),
+        Err(_) if serde_json::from_slice::<serde_json::Value>(&data).is_ok() => Ok(data),
+        Err(e) => Err(e),
+    }
+}
+
+fn read_json<T: for<'de> Deserialize<'de>>(dir: &Path, file: &str) -> Option<T> {
+    let path = dir.join(file);
+    let content = match open_state_file(fs::read(&path).ok()?) {
+        Ok(content) => content,
+        Err(e) => {
+            eprintln!("Failed to decrypt {:?}: {}", path, e);
+            return None;
+        }
+    };
+    match serde_json::from_slice(&content) {
+        Ok(value) => Some(value),
+        Err(e) => {
+            eprintln!("Invalid state in {:?}: {}", path, e);
+            None
+        }
+    }
+}
+
+pub fn save_stable_channel(dir: &Path, sc: &StableChannel) -> Result<(), Box<dyn Error>> {
+    write_json(dir, STABLE_CHANNEL_FILE, &StableChannelState::from_stable_channel(sc))
+}
+
+pub fn load_stable_channel(dir: &Path) -> Option<StableChannelState> {
+    read_json(dir, STABLE_CHANNEL_FILE)
+}
+
+pub fn save_lifecycle(dir: &Path, lifecycle: ChannelLifecycle) -> Result<(), Box<dyn Error>> {
+    write_json(dir, LIFECYCLE_FILE, &lifecycle)
+}
+
+pub fn load_lifecycle(dir: &Path) -> Option<ChannelLifecycle> {
+    read_json(dir, LIFECYCLE_FILE)
+}
+
+pub fn save_onchain_log(dir: &Path, log: &OnchainLog) -> Result<(), Box<dyn Error>> {
+    write_json(dir, ONCHAIN_LOG_FILE, log)
+}
+
+pub fn load_onchain_log(dir: &Path) -> Option<OnchainLog> {
+    read_json(dir, ONCHAIN_LOG_FILE)
+}
+
+/// Whether the user has confirmed backing up the wallet's seed.
+pub fn save_backup_confirmed(dir: &Path, confirmed: bool) -> Result<(), Box<dyn Error>> {
+    write_json(dir, BACKUP_FILE, &confirmed)
+}
+
+pub fn load_backup_confirmed(dir: &Path) -> bool {
+    read_json(dir, BACKUP_FILE).unwrap_or(false)
+}
+
+pub fn save_channel_backup_settings(dir: &Path, settings: &ChannelBackupSettings) -> Result<(), Box<dyn Error>> {
+    write_json(dir, CHANNEL_BACKUP_FILE, settings)
+}
+
+pub fn load_channel_backup_settings(dir: &Path) -> ChannelBackupSettings {
+    read_json(dir, CHANNEL_BACKUP_FILE).unwrap_or_default()
+}
+
+/// Rewrites every state file, so they get encrypted after a passphrase is set.
+pub fn reseal_state_files(dir: &Path) -> Result<(), Box<dyn Error>> {
+    for file in STATE_FILES {
+        if let Some(value) = read_json::<serde_json::Value>(dir, file) {
+            write_json(dir, file, &value)?;
+        }
+    }
+    Ok(())
+}
+
+pub fn save_price_history(dir: &Path, history: &PriceHistory) -> Result<(), Box<dyn Error>> {
+    write_json(dir, PRICE_HISTORY_FILE, history)
+}
+
+pub fn load_price_history(dir: &Path) -> Option<PriceHistory> {
+    read_json(dir, PRICE_HISTORY_FILE)
+}
+
+pub fn save_stabilizations(dir: &Path, records: &[StabilizationRecord]) -> Result<(), Box<dyn Error>> {
+    write_json(dir, STABILIZATIONS_FILE, &records)
+}
+
+pub fn load_stabilizations(dir: &Path) -> Vec<StabilizationRecord> {
+    read_json(dir, STABILIZATIONS_FILE).unwrap_or_default()
+}
@@ -0,0 +1,197 @@
+#[cfg(target_os = "linux")]
+use std::{sync::mpsc, thread, time::Duration};
+
+use tray_icon::menu::{Menu, MenuEvent, MenuId, MenuItem, PredefinedMenuItem};
+use tray_icon::{Icon, TrayIcon, TrayIconBuilder};
+
+use crate::types::USD;
+
+const ICON_SIZE: u32 = 32;
+
+/// How often the GTK thread picks up status updates.
+#[cfg(target_os = "linux")]
+const GTK_UPDATE_INTERVAL: Duration = Duration::from_millis(500);
+
+/// What the user picked from the tray menu.
+pub enum TrayAction {
+    Open,
+    TogglePause,
+    Quit,
+}
+
+/// What the tray shows.
+struct TrayStatus {
+    stable_usd: USD,
+    btc_price: f64,
+    paused: bool,
+}
+
+/// The tray icon, with the stable balance and price in its tooltip and menu.
+///
+/// Linux trays need a GTK main loop, which eframe doesn't run, so there the
+/// icon lives on a thread of its own and gets its updates over a channel.
+pub struct Tray {
+    open: MenuId,
+    pause: MenuId,
+    quit: MenuId,
+    #[cfg(not(target_os = "linux"))]
+    widgets: TrayWidgets,
+    #[cfg(target_os = "linux")]
+    updates: mpsc::Sender<TrayStatus>,
+}
+
+impl Tray {
+    #[cfg(not(target_os = "linux"))]
+    pub fn new() -> Result<Self, String> {
+        let widgets = TrayWidgets::new()?;
+        let (open, pause, quit) = widgets.ids.clone();
+        Ok(Self { open, pause, quit, widgets })
+    }
+
+    #[cfg(target_os = "linux")]
+    pub fn new() -> Result<Self, String> {
+        let (ids_tx, ids_rx) = mpsc::channel();
+        let (updates, updates_rx) = mpsc::channel::<TrayStatus>();
+        thread::spawn(move || {
+            if let Err(e) = gtk::init() {
+                let _ = ids_tx.send(Err(format!("Failed to start GTK for the tray: {}", e)));
+                return;
+            }
+            let widgets = match TrayWidgets::new() {
+                Ok(widgets) => widgets,
+                Err(e) => {
+                    let _ = ids_tx.send(Err(e));
+                    return;
+                }
+            };
+            let _ = ids_tx.send(Ok(widgets.ids.clone()));
+            gtk::glib::timeout_add_local(GTK_UPDATE_INTERVAL, move || loop {
+                match updates_rx.try_recv() {
+                    Ok(status) => widgets.update(&status),
+                    Err(mpsc::TryRecvError::Empty) => return gtk::glib::ControlFlow::Continue,
+                    Err(mpsc::TryRecvError::Disconnected) => {
+                        gtk::main_quit();
+                        return gtk::glib::ControlFlow::Break;
+                    }
+                }
+            });
+            gtk::main();
+        });
+        let (open, pause, quit) = ids_rx
+            .recv()
+            .map_err(|_| "Tray thread exited".to_string())??;
+        Ok(Self { open, pause, quit, updates })
+    }
+
+    pub fn update(&self, stable_usd: USD, btc_price: f64, paused: bool) {
+        let status = TrayStatus {
+            stable_usd,
+            btc_price,
+            paused,
+        };
+        #[cfg(not(target_os = "linux"))]
+        self.widgets.update(&status);
+        #[cfg(target_os = "linux")]
+        if self.updates.send(status).is_err() {
+            eprintln!("Tray thread has exited");
+        }
+    }
+
+    /// The next menu item the user clicked, if any.
+    pub fn poll(&self) -> Option<TrayAction> {
+        while let Ok(event) = MenuEvent::receiver().try_recv() {
+            if event.id == self.open {
+                return Some(TrayAction::Open);
+            } else if event.id == self.pause {
+                return Some(TrayAction::TogglePause);
+            } else if event.id == self.quit {
+                return Some(TrayAction::Quit);
+            }
+        }
+        None
+    }
+}
+
+/// The icon and the menu items whose text changes. Must stay on the thread
+/// that made them.
+struct TrayWidgets {
+    icon: TrayIcon,
+    status: MenuItem,
+    pause: MenuItem,
+    /// Open, pause and quit.
+    ids: (MenuId, MenuId, MenuId),
+}
+
+impl TrayWidgets {
+    fn new() -> Result<Self, String> {
+        let status = MenuItem::new("Stable Channels", false, None);
+        let open = MenuItem::new("Open", true, None);
+        let pause = MenuItem::new("Pause Stabilization", true, None);
+        let quit = MenuItem::new("Quit", true, None);
+
+        let menu = Menu::new();
+        menu.append_items(&[
+            &status,
+            &PredefinedMenuItem::separator(),
+            &open,
+            &pause,
+            &PredefinedMenuItem::separator(),
+            &quit,
+        ])
+        .map_err(|e| format!("Failed to build tray menu: {}", e))?;
+
+        let icon = TrayIconBuilder::new()
+            .with_menu(Box::new(menu))
+            .with_tooltip("Stable Channels")
+            .with_icon(make_icon()?)
+            .build()
+            .map_err(|e| format!("Failed to create tray icon: {}", e))?;
+
+        Ok(Self {
+            icon,
+            ids: (open.id().clone(), pause.id().clone(), quit.id().clone()),
+            status,
+            pause,
+        })
+    }
+
+    fn update(&self, status: &TrayStatus) {
+        let mut text = format!("{} stable · BTC ${:.0}", status.stable_usd, status.btc_price);
+        if status.paused {
+            text.push_str(" · paused");
+        }
+        self.status.set_text(&text);
+        if let Err(e) = self.icon.set_tooltip(Some(format!("Stable Channels\n{}", text))) {
+            eprintln!("Failed to update tray tooltip: {}", e);
+        }
+        self.pause.set_text(if status.paused {
+            "Resume Stabilization"
+        } else {
+            "Pause Stabilization"
+        });
+    }
+}
+
+/// A bitcoin-orange disc with a white ring, drawn so we don't need to ship
+/// an image for the tray.
+fn make_icon() -> Result<Icon, String> {
+    let center = (ICON_SIZE as f32 - 1.0) / 2.0;
+    let radius = ICON_SIZE as f32 / 2.0;
+    let mut rgba = Vec::with_capacity((ICON_SIZE * ICON_SIZE * 4) as usize);
+
+    for y in 0..ICON_SIZE {
+        for x in 0..ICON_SIZE {
+            let distance = ((x as f32 - center).powi(2) + (y as f32 - center).powi(2)).sqrt();
+            let pixel = if distance > radius {
+                [0, 0, 0, 0]
+            } else if distance > radius - 3.0 {
+                [255, 255, 255, 255]
+            } else {
+                [247, 147, 26, 255]
+            };
+            rgba.extend_from_slice(&pixel);
+        }
+    }
+
+    Icon::from_rgba(rgba, ICON_SIZE, ICON_SIZE).map_err(|e| format!("Invalid tray icon: {}", e))
+}
@@ -1,9 +1,13 @@
 use ldk_node::bitcoin::secp256k1::PublicKey;
+use ldk_node::bitcoin::OutPoint;
 use ldk_node::lightning::ln::types::ChannelId;
 use ldk_node::lightning::offers::offer::Offer;
-use std::ops::{Div, Sub};
+use std::ops::{Add, Div, Sub};
 use serde::{Deserialize, Serialize};
 
+use crate::price_feeds::FeedQuote;
+use crate::pricing::PricingMode;
+
 #[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Serialize, Deserialize)]
 pub struct Bitcoin {
     pub sats: u64, // Stored in Satoshis for precision
@@ -24,6 +28,21 @@ impl Bitcoin {
     pub fn to_btc(self) -> f64 {
         self.sats as f64 / Self::SATS_IN_BTC as f64
     }
+
+    pub fn from_usd(usd: USD, btcusd_price: f64) -> Self {
+        if btcusd_price <= 0.0 {
+            return Self::from_sats(0);
+        }
+        Self::from_btc(usd.0 / btcusd_price)
+    }
+}
+
+impl Add for Bitcoin {
+    type Output = Bitcoin;
+
+    fn add(self, other: Bitcoin) -> Bitcoin {
+        Bitcoin::from_sats(self.sats.saturating_add(other.sats))
+    }
 }
 
 impl Sub for Bitcoin {
@@ -70,6 +89,14 @@ impl USD {
     }
 }
 
+impl Add for USD {
+    type Output = USD;
+
+    fn add(self, other: USD) -> USD {
+        USD(self.0 + other.0)
+    }
+}
+
 impl Sub for USD {
     type Output = USD;
 
@@ -100,12 +127,24 @@ impl std::fmt::Display for USD {
     }
 }
 
+/// How much of the stable receiver's channel balance is pegged to USD.
+/// Whatever is not pegged stays in the channel as native bitcoin.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum PegTarget {
+    /// Keep a fixed dollar amount stable.
+    FixedUsd(USD),
+    /// Keep a percentage (0-100) of the channel balance stable.
+    Percent(f64),
+}
+
 // #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct StableChannel {
     pub channel_id: ChannelId,
     pub is_stable_receiver: bool,
     pub counterparty: PublicKey,
+    pub peg_target: PegTarget,
     pub expected_usd: USD,
+    pub native_btc: Bitcoin,
     pub expected_btc: Bitcoin,
     pub stable_receiver_btc: Bitcoin,
     pub stable_provider_btc: Bitcoin,
@@ -116,6 +155,34 @@ pub struct StableChannel {
     pub formatted_datetime: String,
     pub payment_made: bool,
     pub sc_dir: String,
+    /// Price stabilization settles at, as given by `pricing_mode`.
     pub latest_price: f64,
-    pub prices: String
+    /// Median of the latest quotes.
+    pub spot_price: f64,
+    pub pricing_mode: PricingMode,
+    /// `(unix time, spot price)` samples `pricing_mode` averages over.
+    pub price_samples: Vec<(i64, f64)>,
+    /// What each price feed returned on the last fetch.
+    pub prices: Vec<FeedQuote>,
+    /// Unix time `prices` was fetched.
+    pub prices_updated: Option<i64>,
+    /// Funding output of the channel, kept so the closing transaction can be
+    /// found on-chain once LDK forgets the channel.
+    pub funding_txo: Option<OutPoint>,
+    /// Transaction sending the closed channel's funds to the user's address.
+    pub sweep_txid: Option<String>,
+    /// Where to sweep the funds once the channel has closed.
+    pub withdrawal_address: Option<String>,
+    /// Spendable on-chain balance when the channel was closed. Only what
+    /// becomes spendable on top of it is swept, the rest isn't the channel's.
+    pub onchain_before_close_sats: Option<u64>,
+    /// Unix time we were last connected to the counterparty, or the app
+    /// started if later. Not saved.
+    pub counterparty_last_seen: Option<i64>,
+    /// Payment hashes of invoices that add to the stable balance when paid,
+    /// with the amount each adds.
+    pub pending_stable_receives: Vec<([u8; 32], USD)>,
+    /// The counterparty hasn't been told of the current `expected_usd` and
+    /// `native_btc` yet. Stability checks wait for it.
+    pub agreement_pending: bool,
 }
\ No newline at end of file
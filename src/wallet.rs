@@ -0,0 +1,127 @@
+use std::error::Error;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::chain::{confirmed_height, Esplora};
+use crate::recovery::unix_now;
+
+/// On-chain activity the node's wallet doesn't keep a history of: the
+/// addresses we handed out and the transactions we sent.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct OnchainLog {
+    pub addresses: Vec<String>,
+    pub sends: Vec<OnchainSend>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OnchainSend {
+    pub txid: String,
+    pub address: String,
+    /// `None` when the whole balance was sent.
+    pub amount_sats: Option<u64>,
+    pub timestamp: i64,
+}
+
+impl OnchainLog {
+    pub fn record_address(&mut self, address: &str) {
+        if !self.addresses.iter().any(|a| a == address) {
+            self.addresses.push(address.to_string());
+        }
+    }
+
+    pub fn record_send(&mut self, txid: &str, address: &str, amount_sats: Option<u64>) {
+        self.sends.push(OnchainSend {
+            txid: txid.to_string(),
+            address: address.to_string(),
+            amount_sats,
+            timestamp: unix_now(),
+        });
+    }
+}
+
+/// One line of the on-chain transaction history.
+#[derive(Debug, Clone)]
+pub struct OnchainTx {
+    pub txid: String,
+    pub incoming: bool,
+    pub amount_sats: Option<u64>,
+    pub address: String,
+    pub block_height: Option<u32>,
+}
+
+/// Looks up every logged address and send on the chain source. Unconfirmed
+/// transactions come first, then the rest from newest to oldest.
+///
+/// This is only what went through the app's own addresses and sends: ldk-node
+/// doesn't list the wallet's transactions, so channel funding, closes and the
+/// change LDK keeps are missing.
+pub fn fetch_history(esplora: &Esplora, log: &OnchainLog) -> Result<Vec<OnchainTx>, Box<dyn Error>> {
+    let mut history: Vec<OnchainTx> = Vec::new();
+
+    for send in &log.sends {
+        let status = esplora.tx_status(&send.txid)?;
+        history.push(OnchainTx {
+            txid: send.txid.clone(),
+            incoming: false,
+            amount_sats: send.amount_sats,
+            address: send.address.clone(),
+            block_height: status.block_height,
+        });
+    }
+
+    for address in &log.addresses {
+        for tx in esplora.address_txs(address)? {
+            let Some(txid) = tx.get("txid").and_then(|t| t.as_str()) else {
+                continue;
+            };
+            // Our own change coming back isn't a receive.
+            if history.iter().any(|h| h.txid == txid) {
+                continue;
+            }
+            // Spends from the address, e.g. a channel funded from a deposit,
+            // pay nothing to it and aren't a receive either.
+            let received = received_sats(&tx, address);
+            if received == 0 {
+                continue;
+            }
+            history.push(OnchainTx {
+                txid: txid.to_string(),
+                incoming: true,
+                amount_sats: Some(received),
+                address: address.clone(),
+                block_height: tx.get("status").and_then(confirmed_height),
+            });
+        }
+    }
+
+    history.sort_by_key(|tx| std::cmp::Reverse(tx.block_height.unwrap_or(u32::MAX)));
+    Ok(history)
+}
+
+/// Sats paid to `address`, as (confirmed, unconfirmed).
+pub fn received_at(esplora: &Esplora, address: &str) -> Result<(u64, u64), Box<dyn Error>> {
+    let mut confirmed = 0;
+    let mut unconfirmed = 0;
+    for tx in esplora.address_txs(address)? {
+        if tx.get("status").and_then(confirmed_height).is_some() {
+            confirmed += received_sats(&tx, address);
+        } else {
+            unconfirmed += received_sats(&tx, address);
+        }
+    }
+    Ok((confirmed, unconfirmed))
+}
+
+fn received_sats(tx: &Value, address: &str) -> u64 {
+    tx.get("vout")
+        .and_then(|vout| vout.as_array())
+        .map(|outputs| {
+            outputs
+                .iter()
+                .filter(|o| o.get("scriptpubkey_address").and_then(|a| a.as_str()) == Some(address))
+                .filter_map(|o| o.get("value").and_then(|v| v.as_u64()))
+                .sum()
+        })
+        .unwrap_or(0)
+}